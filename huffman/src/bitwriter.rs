@@ -4,7 +4,7 @@ use std::{env, fs};
 use std::fs::File;
 use std::io::{Read, Write, Cursor, Seek, SeekFrom};
 use std::path::Path;
-use crate::lz77::LZ77;
+use crate::lz77::{CompressionLevel, LZ77, Token};
 
 
 fn read_file_to_byte_vector(file_path: &Path) -> io::Result<Vec<u8>> {
@@ -16,8 +16,9 @@ fn read_file_to_byte_vector(file_path: &Path) -> io::Result<Vec<u8>> {
 
 pub struct BitWriter {
     buffer: Vec<u8>,
-    position: usize,
-    vec_bool: Vec<bool>,
+    bit_buffer: u64, // pending bits, the next bit of the stream is the lowest bit
+    bit_count: u32,  // number of pending bits in bit_buffer
+    level: CompressionLevel,
 }
 
 pub fn convert_to_fixed_huffman_code(real_value: u8) -> u16 {
@@ -66,10 +67,7 @@ pub fn convert_length_to_fixed_huffman_code_step_2(code_value: u16) -> u16 {
 
 pub fn convert_distance_to_fixed_huffman_code(real_value: u16) -> (u16, u16, u16) {
     assert!(real_value >= 1);
-    if real_value > 32768 {
-        println!("Distance value is too large: {}", real_value);
-    }
-    assert!(real_value <= 32768);
+    assert!(real_value <= 32768, "Distance value is too large: {}", real_value);
     if real_value < 5 {
         return (real_value - 1, 0, 0);
     }
@@ -103,38 +101,395 @@ pub fn get_fixed_huffman_code_length_for_u16(code_value: u16) -> u8 {
 
 
 
+// order in which the code length code lengths are written (RFC 1951, 3.2.7)
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+pub fn count_token_frequencies(tokens: &[Token]) -> (Vec<u32>, Vec<u32>) {
+    let mut hlit_freqs = vec![0u32; 286];
+    let mut hdist_freqs = vec![0u32; 30];
+    for token in tokens {
+        match *token {
+            Token::Literal(value) => hlit_freqs[value as usize] += 1,
+            Token::Match(length, distance) => {
+                let (code_value, _, _) = convert_length_to_fixed_huffman_code(length);
+                hlit_freqs[code_value as usize] += 1;
+                let (code_value, _, _) = convert_distance_to_fixed_huffman_code(distance);
+                hdist_freqs[code_value as usize] += 1;
+            }
+        }
+    }
+    hlit_freqs[256] = 1; // EOB
+    (hlit_freqs, hdist_freqs)
+}
+
+// code lengths limited to max_bits using the package-merge algorithm
+pub fn get_length_limited_code_lengths(freqs: &[u32], max_bits: usize) -> Vec<u8> {
+    let mut lengths = vec![0u8; freqs.len()];
+    let mut leaves: Vec<(u64, usize)> = freqs.iter().enumerate()
+        .filter(|(_, &freq)| freq > 0)
+        .map(|(symbol, &freq)| (freq as u64, symbol))
+        .collect();
+    if leaves.is_empty() {
+        return lengths;
+    }
+    if leaves.len() == 1 {
+        lengths[leaves[0].1] = 1;
+        return lengths;
+    }
+    assert!(leaves.len() <= 1 << max_bits);
+    leaves.sort();
+
+    // every item is a weight and the leaves it contains
+    let leaf_items: Vec<(u64, Vec<usize>)> = leaves.iter().map(|&(freq, symbol)| (freq, vec![symbol])).collect();
+    let mut items = leaf_items.clone();
+    for _ in 1..max_bits {
+        let mut packages: Vec<(u64, Vec<usize>)> = Vec::new();
+        for pair in items.chunks_exact(2) {
+            let mut symbols = pair[0].1.clone();
+            symbols.extend(&pair[1].1);
+            packages.push((pair[0].0 + pair[1].0, symbols));
+        }
+        let mut merged = Vec::with_capacity(leaf_items.len() + packages.len());
+        let (mut i, mut j) = (0, 0);
+        while i < leaf_items.len() || j < packages.len() {
+            if j == packages.len() || (i < leaf_items.len() && leaf_items[i].0 <= packages[j].0) {
+                merged.push(leaf_items[i].clone());
+                i += 1;
+            } else {
+                merged.push(packages[j].clone());
+                j += 1;
+            }
+        }
+        items = merged;
+    }
+
+    for (_, symbols) in items.iter().take(2 * leaves.len() - 2) {
+        for &symbol in symbols {
+            lengths[symbol] += 1;
+        }
+    }
+    lengths
+}
+
+// canonical huffman codes (RFC 1951, 3.2.2), to be written msb first
+pub fn get_canonical_huffman_codes(lengths: &[u8]) -> Vec<u16> {
+    let max_length = *lengths.iter().max().unwrap_or(&0) as usize;
+    let mut bl_count = vec![0u16; max_length + 1];
+    for &length in lengths {
+        bl_count[length as usize] += 1;
+    }
+    bl_count[0] = 0;
+
+    let mut next_codes = vec![0u16; max_length + 1];
+    let mut code = 0;
+    for bits in 1..=max_length {
+        code = (code + bl_count[bits - 1]) << 1;
+        next_codes[bits] = code;
+    }
+
+    let mut codes = vec![0u16; lengths.len()];
+    for (symbol, &length) in lengths.iter().enumerate() {
+        if length != 0 {
+            codes[symbol] = next_codes[length as usize];
+            next_codes[length as usize] += 1;
+        }
+    }
+    codes
+}
+
+// number of codes to transmit, dropping trailing unused codes
+fn get_num_used_codes(lengths: &[u8], minimum: usize) -> usize {
+    let mut num = lengths.len();
+    while num > minimum && lengths[num - 1] == 0 {
+        num -= 1;
+    }
+    num
+}
+
+// (symbol, extra bits count, extra bits value) using 16/17/18 for runs
+pub fn run_length_encode_code_lengths(lengths: &[u8]) -> Vec<(u8, u8, u16)> {
+    let mut result: Vec<(u8, u8, u16)> = Vec::new();
+    let mut i = 0;
+    while i < lengths.len() {
+        let length = lengths[i];
+        let mut run = 1;
+        while i + run < lengths.len() && lengths[i + run] == length {
+            run += 1;
+        }
+        i += run;
+
+        if length == 0 {
+            while run >= 11 {
+                let count = run.min(138);
+                result.push((18, 7, (count - 11) as u16));
+                run -= count;
+            }
+            if run >= 3 {
+                result.push((17, 3, (run - 3) as u16));
+                run = 0;
+            }
+        } else {
+            result.push((length, 0, 0));
+            run -= 1;
+            while run >= 3 {
+                let count = run.min(6);
+                result.push((16, 2, (count - 3) as u16));
+                run -= count;
+            }
+        }
+        for _ in 0..run {
+            result.push((length, 0, 0));
+        }
+    }
+    result
+}
+
+// bits of the extra length and distance bits of all matches
+fn get_extra_bits_cost(tokens: &[Token]) -> u64 {
+    let mut cost = 0;
+    for token in tokens {
+        if let Token::Match(length, distance) = *token {
+            let (_, length_extra_bits, _) = convert_length_to_fixed_huffman_code(length);
+            let (_, distance_extra_bits, _) = convert_distance_to_fixed_huffman_code(distance);
+            cost += (length_extra_bits + distance_extra_bits) as u64;
+        }
+    }
+    cost
+}
+
+// bits of a fixed huffman block including its header and end of block code
+fn get_fixed_block_cost(tokens: &[Token]) -> u64 {
+    let mut cost = 3 + 7;
+    for token in tokens {
+        cost += match *token {
+            Token::Literal(value) => get_fixed_huffman_code_length(value) as u64,
+            Token::Match(length, _) => {
+                let (code_value, _, _) = convert_length_to_fixed_huffman_code(length);
+                get_fixed_huffman_code_length_for_u16(code_value) as u64 + 5
+            }
+        };
+    }
+    cost + get_extra_bits_cost(tokens)
+}
+
+// at most this many tokens go into one block
+const MAX_BLOCK_TOKENS: usize = 16384;
+// blocks are not ended early before they have this many tokens
+const MIN_BLOCK_TOKENS: usize = 2048;
+// the statistics are compared after this many new tokens
+const OBSERVATION_INTERVAL: usize = 512;
+// 8 kinds of literals and short or long matches
+const NUM_OBSERVATION_TYPES: usize = 10;
+
+fn get_token_length(token: &Token) -> usize {
+    match *token {
+        Token::Literal(_) => 1,
+        Token::Match(length, _) => length as usize,
+    }
+}
+
+fn get_observation_type(token: &Token) -> usize {
+    match *token {
+        Token::Literal(value) => (((value >> 5) & 0x6) | (value & 1)) as usize,
+        Token::Match(length, _) => 8 + (length >= 9) as usize,
+    }
+}
+
+// true if the new observations are distributed clearly differently from the block so far
+fn statistics_changed(observations: &[u32], num_observations: u32, new_observations: &[u32], num_new: u32) -> bool {
+    let mut total_delta: u64 = 0;
+    for (&old, &new) in observations.iter().zip(new_observations) {
+        let expected = old as u64 * num_new as u64;
+        let actual = new as u64 * num_observations as u64;
+        total_delta += expected.abs_diff(actual);
+    }
+    // differences above about 40 percent of the observations end the block
+    total_delta * 512 >= num_new as u64 * num_observations as u64 * 200
+}
+
+// the end index of every block the tokens are split into; a block ends after
+// MAX_BLOCK_TOKENS tokens or earlier when the kinds of literals and matches change
+fn split_into_blocks(tokens: &[Token]) -> Vec<usize> {
+    let mut block_ends = Vec::new();
+    let mut block_start = 0;
+    let mut observations = [0u32; NUM_OBSERVATION_TYPES];
+    let mut num_observations = 0;
+    let mut new_observations = [0u32; NUM_OBSERVATION_TYPES];
+    let mut num_new = 0;
+
+    for (index, token) in tokens.iter().enumerate() {
+        new_observations[get_observation_type(token)] += 1;
+        num_new += 1;
+        let end = index + 1;
+
+        if num_new as usize == OBSERVATION_INTERVAL {
+            let new_start = end - num_new as usize;
+            if new_start - block_start >= MIN_BLOCK_TOKENS
+                && statistics_changed(&observations, num_observations, &new_observations, num_new) {
+                // the new tokens start the next block
+                block_ends.push(new_start);
+                block_start = new_start;
+                observations = [0; NUM_OBSERVATION_TYPES];
+                num_observations = 0;
+            }
+            for (old, new) in observations.iter_mut().zip(new_observations.iter_mut()) {
+                *old += *new;
+                *new = 0;
+            }
+            num_observations += num_new;
+            num_new = 0;
+        }
+
+        if end - block_start == MAX_BLOCK_TOKENS && end < tokens.len() {
+            block_ends.push(end);
+            block_start = end;
+            observations = [0; NUM_OBSERVATION_TYPES];
+            num_observations = 0;
+            new_observations = [0; NUM_OBSERVATION_TYPES];
+            num_new = 0;
+        }
+    }
+    block_ends.push(tokens.len());
+    block_ends
+}
+
+enum BlockType {
+    Stored,
+    Fixed,
+    Dynamic(Box<DynamicCodes>),
+}
+
+// the codes of a dynamic huffman block and its run length encoded code lengths
+struct DynamicCodes {
+    hlit_freqs: Vec<u32>,
+    hdist_freqs: Vec<u32>,
+    hlit_code: HuffmanCode,
+    hdist_code: HuffmanCode,
+    hclen_code: HuffmanCode,
+    hlit: usize,
+    hdist: usize,
+    hclen: usize,
+    rle_symbols: Vec<(u8, u8, u16)>,
+}
+
+impl DynamicCodes {
+    fn from_tokens(tokens: &[Token]) -> Self {
+        let (hlit_freqs, hdist_freqs) = count_token_frequencies(tokens);
+        let hlit_code = HuffmanCode::from_frequencies(&hlit_freqs, 15);
+        let hdist_code = HuffmanCode::from_frequencies(&hdist_freqs, 15);
+        let hlit = get_num_used_codes(&hlit_code.lengths, 257);
+        let hdist = get_num_used_codes(&hdist_code.lengths, 1);
+
+        // the literal/length and distance lengths are run length encoded separately
+        let mut rle_symbols = run_length_encode_code_lengths(&hlit_code.lengths[..hlit]);
+        rle_symbols.extend(run_length_encode_code_lengths(&hdist_code.lengths[..hdist]));
+
+        let mut hclen_freqs = vec![0u32; 19];
+        for &(symbol, _, _) in &rle_symbols {
+            hclen_freqs[symbol as usize] += 1;
+        }
+        let hclen_code = HuffmanCode::from_frequencies(&hclen_freqs, 7);
+        let mut hclen = 19;
+        while hclen > 4 && hclen_code.lengths[CODE_LENGTH_ORDER[hclen - 1]] == 0 {
+            hclen -= 1;
+        }
+
+        DynamicCodes { hlit_freqs, hdist_freqs, hlit_code, hdist_code, hclen_code, hlit, hdist, hclen, rle_symbols }
+    }
+
+    // bits of the whole block, header and end of block code included
+    fn get_block_cost(&self, tokens: &[Token]) -> u64 {
+        let mut cost = 3 + 5 + 5 + 4 + 3 * self.hclen as u64;
+        for &(symbol, extra_bits, _) in &self.rle_symbols {
+            cost += self.hclen_code.lengths[symbol as usize] as u64 + extra_bits as u64;
+        }
+        for (freq, length) in self.hlit_freqs.iter().zip(&self.hlit_code.lengths) {
+            cost += *freq as u64 * *length as u64;
+        }
+        for (freq, length) in self.hdist_freqs.iter().zip(&self.hdist_code.lengths) {
+            cost += *freq as u64 * *length as u64;
+        }
+        cost + get_extra_bits_cost(tokens)
+    }
+}
+
+pub struct HuffmanCode {
+    lengths: Vec<u8>,
+    codes: Vec<u16>,
+}
+
+impl HuffmanCode {
+    // at least two codes are always assigned so that every decoder accepts the tree
+    pub fn from_frequencies(freqs: &[u32], max_bits: usize) -> Self {
+        let mut freqs = freqs.to_vec();
+        let mut num_used = freqs.iter().filter(|&&freq| freq > 0).count();
+        for freq in freqs.iter_mut() {
+            if num_used >= 2 {
+                break;
+            }
+            if *freq == 0 {
+                *freq = 1;
+                num_used += 1;
+            }
+        }
+        let lengths = get_length_limited_code_lengths(&freqs, max_bits);
+        let codes = get_canonical_huffman_codes(&lengths);
+        HuffmanCode { lengths, codes }
+    }
+
+    fn write_symbol(&self, bitwriter: &mut BitWriter, symbol: usize) -> io::Result<()> {
+        bitwriter.write_single_general(self.codes[symbol], self.lengths[symbol], false)
+    }
+}
+
 impl BitWriter {
     pub fn new() -> Self {
+        BitWriter::with_level(CompressionLevel::default())
+    }
+
+    pub fn with_level(level: CompressionLevel) -> Self {
         BitWriter {
             buffer: Vec::new(),
-            position: 0,
-            vec_bool: Vec::new(),
+            bit_buffer: 0,
+            bit_count: 0,
+            level,
         }
     }
 
-    fn write_header(&mut self) {
-        self.vec_bool.push(true);
-
-        // 10 for fixed huffman (reverse order)
-        self.vec_bool.push(true);
-        self.vec_bool.push(false);
-        self.position += 3;
+    // btype is 0 for stored, 1 for fixed huffman, 2 for dynamic huffman (written lsb first)
+    fn write_block_header(&mut self, bfinal: bool, btype: u16) -> io::Result<()> {
+        self.write_single_general(bfinal as u16, 1, true)?;
+        self.write_single_general(btype, 2, true)
     }
 
+    // reverse = true writes the value lsb first (extra bits, header fields),
+    // reverse = false writes it msb first (huffman codes)
     fn write_single_general(&mut self, value: u16, count: u8, reverse: bool) -> io::Result<()> {
-        for i in 0..count {
-            if reverse {
-                let cur_bit = (value >> i) & 1; // push the rightmost bit
-                self.vec_bool.push(cur_bit == 1);
-            } else {
-                let cur_bit = (value >> (count - i - 1)) & 1; // push the leftmost bit
-                self.vec_bool.push(cur_bit == 1);
-            }
+        if count == 0 {
+            return Ok(());
+        }
+        let bits = if reverse {
+            value & (((1u32 << count) - 1) as u16)
+        } else {
+            value.reverse_bits() >> (16 - count)
+        };
+        self.bit_buffer |= (bits as u64) << self.bit_count;
+        self.bit_count += count as u32;
+        if self.bit_count >= 48 {
+            self.flush_whole_bytes();
         }
-        self.position += count as usize;
         Ok(())
     }
 
+    // move the complete bytes of bit_buffer into buffer
+    fn flush_whole_bytes(&mut self) {
+        while self.bit_count >= 8 {
+            self.buffer.push(self.bit_buffer as u8);
+            self.bit_buffer >>= 8;
+            self.bit_count -= 8;
+        }
+    }
+
     pub fn write_single_literal(&mut self, value: u8) -> io::Result<()> {
         let count: u8 = get_fixed_huffman_code_length(value);
         let huffman_code = convert_to_fixed_huffman_code(value);
@@ -158,90 +513,308 @@ impl BitWriter {
         let (code_value, extra_bits, extra_value) = convert_distance_to_fixed_huffman_code(distance_value);
         self.write_single_general(code_value, count, false)?;
         if extra_bits != 0 {
-            // println!("extra bits: {}, extra value: {}", extra_bits, extra_value);
             self.write_single_general(extra_value, extra_bits as u8, true)?;
         }
         Ok(())
     }
 
-    // big endian
-    // pub fn write_bitstream(&mut self) -> io::Result<()> {
-    //     let mut byte: u8 = 0;
-    //     let mut count: u8 = 0;
-    //     for bit in &self.vec_bool {
-    //         byte = byte << 1;
-    //         if *bit {
-    //             byte = byte | 1;
-    //         }
-    //         count += 1;
-    //         if count == 8 {
-    //             self.buffer.push(byte);
-    //             byte = 0;
-    //             count = 0;
-    //         }
-    //     }
-    //     if count != 0 {
-    //         byte = byte << (8 - count);
-    //         self.buffer.push(byte);
-    //     }
-    //     Ok(())
-    // }
-
-    // little endian 
-    fn write_bitstream_buffer_little_endian(&mut self) -> io::Result<()> {
-        let mut byte: u8 = 0;
-        let mut count: u8 = 0;
-        for bit in &self.vec_bool {
-            if *bit {
-                byte |= 1 << count;  
+    fn write_eob(&mut self) -> io::Result<()> {
+        self.write_single_general(0, 7, false)
+    }
+
+    fn write_token_fixed_huffman(&mut self, token: &Token) -> io::Result<()> {
+        match *token {
+            Token::Literal(value) => self.write_single_literal(value),
+            Token::Match(length, distance) => {
+                self.write_length(length)?;
+                self.write_distance(distance)
+            }
+        }
+    }
+
+    fn write_token_dynamic_huffman(&mut self, token: &Token, hlit_code: &HuffmanCode, hdist_code: &HuffmanCode) -> io::Result<()> {
+        match *token {
+            Token::Literal(value) => hlit_code.write_symbol(self, value as usize),
+            Token::Match(length, distance) => {
+                let (code_value, extra_bits, extra_value) = convert_length_to_fixed_huffman_code(length);
+                hlit_code.write_symbol(self, code_value as usize)?;
+                if extra_bits != 0 {
+                    self.write_single_general(extra_value, extra_bits as u8, true)?;
+                }
+                let (code_value, extra_bits, extra_value) = convert_distance_to_fixed_huffman_code(distance);
+                hdist_code.write_symbol(self, code_value as usize)?;
+                if extra_bits != 0 {
+                    self.write_single_general(extra_value, extra_bits as u8, true)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn write_fixed_block(&mut self, tokens: &[Token], bfinal: bool) -> io::Result<()> {
+        self.write_block_header(bfinal, 1)?;
+        for token in tokens {
+            self.write_token_fixed_huffman(token)?;
+        }
+        self.write_eob()
+    }
+
+    fn write_dynamic_block(&mut self, tokens: &[Token], bfinal: bool) -> io::Result<()> {
+        let codes = DynamicCodes::from_tokens(tokens);
+        self.write_dynamic_block_with_codes(tokens, &codes, bfinal)
+    }
+
+    fn write_dynamic_block_with_codes(&mut self, tokens: &[Token], codes: &DynamicCodes, bfinal: bool) -> io::Result<()> {
+        self.write_block_header(bfinal, 2)?;
+        self.write_single_general((codes.hlit - 257) as u16, 5, true)?;
+        self.write_single_general((codes.hdist - 1) as u16, 5, true)?;
+        self.write_single_general((codes.hclen - 4) as u16, 4, true)?;
+        for &alphabet in CODE_LENGTH_ORDER.iter().take(codes.hclen) {
+            self.write_single_general(codes.hclen_code.lengths[alphabet] as u16, 3, true)?;
+        }
+        for &(symbol, extra_bits, extra_value) in &codes.rle_symbols {
+            codes.hclen_code.write_symbol(self, symbol as usize)?;
+            if extra_bits != 0 {
+                self.write_single_general(extra_value, extra_bits, true)?;
             }
-            count += 1;
-            if count == 8 {
-                self.buffer.push(byte);
-                byte = 0;
-                count = 0;
+        }
+
+        for token in tokens {
+            self.write_token_dynamic_huffman(token, &codes.hlit_code, &codes.hdist_code)?;
+        }
+        codes.hlit_code.write_symbol(self, 256)
+    }
+
+    // bits of stored blocks holding len bytes, starting at the current bit position
+    fn get_stored_block_cost(&self, len: usize) -> u64 {
+        let mut bit_position = (self.bit_count % 8) as u64;
+        let mut remaining = len;
+        loop {
+            let chunk = remaining.min(65535);
+            // header, padding to the byte boundary, LEN and NLEN, data
+            bit_position += 3;
+            bit_position = bit_position.div_ceil(8) * 8;
+            bit_position += 32 + 8 * chunk as u64;
+            remaining -= chunk;
+            if remaining == 0 {
+                break;
             }
         }
-        if count != 0 {
-            self.buffer.push(byte);  
+        bit_position - (self.bit_count % 8) as u64
+    }
+    // stored blocks hold at most 65535 bytes each, only the last one gets bfinal
+    fn write_stored_block(&mut self, data: &[u8], bfinal: bool) -> io::Result<()> {
+        let mut chunks: Vec<&[u8]> = data.chunks(65535).collect();
+        if chunks.is_empty() {
+            chunks.push(&[]);
+        }
+        let last = chunks.len() - 1;
+        for (index, chunk) in chunks.into_iter().enumerate() {
+            self.write_block_header(bfinal && index == last, 0)?;
+            // LEN and NLEN start at the next byte boundary
+            self.flush_whole_bytes();
+            if self.bit_count > 0 {
+                self.buffer.push(self.bit_buffer as u8);
+                self.bit_buffer = 0;
+                self.bit_count = 0;
+            }
+            let len = chunk.len() as u16;
+            self.buffer.extend(&len.to_le_bytes());
+            self.buffer.extend(&(!len).to_le_bytes());
+            self.buffer.extend_from_slice(chunk);
         }
         Ok(())
     }
-    
 
-    fn write_eob(&mut self) -> io::Result<()> {
-        for _ in 0..7 {
-            self.vec_bool.push(false);
+    // the cheapest encoding of tokens given the bits they take as stored data
+    fn choose_block_type(&self, tokens: &[Token], stored_cost: u64) -> BlockType {
+        let fixed_cost = get_fixed_block_cost(tokens);
+        if self.level.dynamic_blocks {
+            let codes = DynamicCodes::from_tokens(tokens);
+            let dynamic_cost = codes.get_block_cost(tokens);
+            if dynamic_cost < fixed_cost && dynamic_cost < stored_cost {
+                return BlockType::Dynamic(Box::new(codes));
+            }
+        }
+        if fixed_cost < stored_cost {
+            BlockType::Fixed
+        } else {
+            BlockType::Stored
+        }
+    }
+
+    // encode data[start..] as one or more blocks, data[..start] is only searched for matches
+    fn write_data_block(&mut self, data: &[u8], start: usize, bfinal: bool) -> io::Result<()> {
+        if self.level.store {
+            return self.write_stored_block(&data[start..], bfinal);
+        }
+        let mut compressor = LZ77::with_level(WINDOW_SIZE, 258, &self.level);
+        let mut tokens: Vec<Token> = Vec::new();
+        compressor.compress_from(data, start, &mut tokens);
+
+        // consecutive blocks chosen to be stored are written together, which
+        // saves the stored block headers between them
+        let mut stored_start: Option<usize> = None;
+        let mut token_start = 0;
+        let mut byte_start = start;
+        for token_end in split_into_blocks(&tokens) {
+            let block_tokens = &tokens[token_start..token_end];
+            let byte_end = byte_start + block_tokens.iter().map(get_token_length).sum::<usize>();
+            let stored_cost = match stored_start {
+                Some(stored_start) => self.get_stored_block_cost(byte_end - stored_start)
+                    - self.get_stored_block_cost(byte_start - stored_start),
+                None => self.get_stored_block_cost(byte_end - byte_start),
+            };
+            let block_type = self.choose_block_type(block_tokens, stored_cost);
+            if let BlockType::Stored = block_type {
+                stored_start.get_or_insert(byte_start);
+            } else {
+                if let Some(stored_start) = stored_start.take() {
+                    self.write_stored_block(&data[stored_start..byte_start], false)?;
+                }
+                let last = bfinal && token_end == tokens.len();
+                match block_type {
+                    BlockType::Dynamic(codes) => self.write_dynamic_block_with_codes(block_tokens, &codes, last)?,
+                    _ => self.write_fixed_block(block_tokens, last)?,
+                }
+            }
+            token_start = token_end;
+            byte_start = byte_end;
+        }
+        if let Some(stored_start) = stored_start {
+            self.write_stored_block(&data[stored_start..], bfinal)?;
+        }
+        Ok(())
+    }
+
+    // pad the last byte with zeros and move it into the buffer
+    fn finish_bitstream(&mut self) -> io::Result<()> {
+        self.flush_whole_bytes();
+        if self.bit_count > 0 {
+            self.buffer.push(self.bit_buffer as u8);
+            self.bit_buffer = 0;
+            self.bit_count = 0;
         }
         Ok(())
     }
 
     // the function to call
+    pub fn write_bitstream(&mut self, data: &[u8]) -> io::Result<()> {
+        self.write_data_block(data, 0, true)?;
+        self.finish_bitstream()
+    }
+
     pub fn write_bitstream_fixed_huffman(&mut self, data: &Vec<u8>) -> io::Result<()> {
-        let mut compressor = LZ77::new(32768, 258);
+        let mut compressor = LZ77::with_level(32768, 258, &self.level);
+        let mut tokens: Vec<Token> = Vec::new();
+        compressor.compress(data, &mut tokens);
 
-        self.write_header();
-        compressor.compress(data, self);
-        
-        self.write_eob()?;
-        // check if the last byte is full
-        if self.vec_bool.len() % 8 != 0 {
-            let mut count = self.vec_bool.len() % 8;
-            while count < 8 {
-                self.vec_bool.push(false);
-                count += 1;
+        self.write_fixed_block(&tokens, true)?;
+        self.finish_bitstream()
+    }
+
+    pub fn write_bitstream_dynamic_huffman(&mut self, data: &[u8]) -> io::Result<()> {
+        let mut compressor = LZ77::with_level(32768, 258, &self.level);
+        let mut tokens: Vec<Token> = Vec::new();
+        compressor.compress(data, &mut tokens);
+
+        self.write_dynamic_block(&tokens, true)?;
+        self.finish_bitstream()
+    }
+
+    pub fn get_buffer(&self) -> &Vec<u8> {
+        &self.buffer
+    }
+
+    // for debug: the finished bitstream one bool per bit
+    pub fn get_vec_bool(&self) -> Vec<bool> {
+        let mut result: Vec<bool> = Vec::with_capacity(self.buffer.len() * 8);
+        for byte in &self.buffer {
+            for i in 0..8 {
+                result.push((byte >> i) & 1 == 1);
             }
         }
-        self.write_bitstream_buffer_little_endian()?;
+        result
+    }
+}
+
+// input bytes compressed at once by default
+const BLOCK_SIZE: usize = 1 << 16;
+// matches reach at most this far back
+const WINDOW_SIZE: usize = 32768;
+
+// compresses data written to it into a deflate stream on writer, block_size bytes of
+// input at a time, each split into one or more blocks; finish must be called to write
+// the last block
+pub struct DeflateEncoder<W: Write> {
+    writer: W,
+    bit_writer: BitWriter,
+    block_size: usize,
+    data: Vec<u8>,       // up to WINDOW_SIZE bytes already compressed, then the pending input
+    history_len: usize,  // number of already compressed bytes at the start of data
+}
+
+impl<W: Write> DeflateEncoder<W> {
+    pub fn new(writer: W) -> Self {
+        DeflateEncoder::with_level(writer, CompressionLevel::default())
+    }
+
+    pub fn with_level(writer: W, level: CompressionLevel) -> Self {
+        DeflateEncoder::with_block_size(writer, level, BLOCK_SIZE)
+    }
+
+    pub fn with_block_size(writer: W, level: CompressionLevel, block_size: usize) -> Self {
+        DeflateEncoder {
+            writer,
+            bit_writer: BitWriter::with_level(level),
+            block_size: block_size.max(1),
+            data: Vec::new(),
+            history_len: 0,
+        }
+    }
+
+    // compress the pending input into one block and write out its complete bytes
+    fn write_block(&mut self, bfinal: bool) -> io::Result<()> {
+        self.bit_writer.write_data_block(&self.data, self.history_len, bfinal)?;
+
+        // the last WINDOW_SIZE bytes stay for matches of the next block
+        let discard = self.data.len().saturating_sub(WINDOW_SIZE);
+        self.data.drain(..discard);
+        self.history_len = self.data.len();
+        self.write_buffer()
+    }
+
+    fn write_buffer(&mut self) -> io::Result<()> {
+        self.bit_writer.flush_whole_bytes();
+        self.writer.write_all(&self.bit_writer.buffer)?;
+        self.bit_writer.buffer.clear();
         Ok(())
     }
 
-    pub fn get_buffer(&self) -> &Vec<u8> {
-        &self.buffer
+    // write the final block and return the writer
+    pub fn finish(mut self) -> io::Result<W> {
+        self.write_block(true)?;
+        self.bit_writer.finish_bitstream()?;
+        self.write_buffer()?;
+        self.writer.flush()?;
+        Ok(self.writer)
     }
+}
 
-    pub fn get_vec_bool(&self) -> &Vec<bool> {
-        &self.vec_bool
+impl<W: Write> Write for DeflateEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let pending = self.data.len() - self.history_len;
+        let count = buf.len().min(self.block_size - pending);
+        self.data.extend_from_slice(&buf[..count]);
+        if pending + count == self.block_size {
+            self.write_block(false)?;
+        }
+        Ok(count)
+    }
+
+    // only bytes of completed blocks are passed on, the pending input stays until its block is full
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
     }
 }
 
@@ -249,6 +822,31 @@ impl BitWriter {
 mod tests {
     use super::*;
 
+    // run with: cargo test --release -p huffman bench -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn bench_write_bits() {
+        let file_names = ["../testdata/generic_data/cowsay/cowsay.txt",
+                          "../testdata/generic_data/bbrot/bbrot.pgm",
+                          "../testdata/generic_data/zip/zip"];
+        for file_name in file_names.iter() {
+            let data = read_file_to_byte_vector(Path::new(file_name)).unwrap();
+
+            let iterations = 20;
+            let start = std::time::Instant::now();
+            for _ in 0..iterations {
+                let mut bit_writer = BitWriter::new();
+                for &value in &data {
+                    bit_writer.write_single_literal(value).unwrap();
+                }
+                bit_writer.finish_bitstream().unwrap();
+                assert!(bit_writer.get_buffer().len() >= data.len());
+            }
+            let seconds = start.elapsed().as_secs_f64();
+            println!("{}: {:.2} MB/s", file_name, (data.len() * iterations) as f64 / seconds / 1e6);
+        }
+    }
+
     #[test]
     fn test_convert_to_fixed_huffman_code() {
         assert_eq!(convert_to_fixed_huffman_code(0), 48);
@@ -280,7 +878,7 @@ mod tests {
         bit_writer.write_bitstream_fixed_huffman(&data).unwrap();
         let buffer = bit_writer.get_buffer();
         println!("{:?}", buffer);
-        let vec_bool = bit_writer.get_vec_bool();
+        let vec_bool = &bit_writer.get_vec_bool();
         // convert vec_bool to vector of 0 and 1
         let mut vec_u8: Vec<u8> = Vec::new();
         for bit in vec_bool {
@@ -303,7 +901,7 @@ mod tests {
         bit_writer.write_bitstream_fixed_huffman(&data).unwrap();
         let buffer = bit_writer.get_buffer();
         println!("{:?}", buffer);
-        let vec_bool = bit_writer.get_vec_bool();
+        let vec_bool = &bit_writer.get_vec_bool();
         // convert vec_bool to vector of 0 and 1
         let mut vec_u8: Vec<u8> = Vec::new();
         for bit in vec_bool {
@@ -327,7 +925,7 @@ mod tests {
         bit_writer.write_bitstream_fixed_huffman(&data).unwrap();
         let buffer = bit_writer.get_buffer();
         println!("{:?}", buffer);
-        let vec_bool = bit_writer.get_vec_bool();
+        let vec_bool = &bit_writer.get_vec_bool();
         // convert vec_bool to vector of 0 and 1
         let mut vec_u8: Vec<u8> = Vec::new();
         for bit in vec_bool {
@@ -350,7 +948,7 @@ mod tests {
         bit_writer.write_bitstream_fixed_huffman(&data).unwrap();
         let buffer = bit_writer.get_buffer();
         println!("{:?}", buffer);
-        let vec_bool = bit_writer.get_vec_bool();
+        let vec_bool = &bit_writer.get_vec_bool();
         // convert vec_bool to vector of 0 and 1
         let mut vec_u8: Vec<u8> = Vec::new();
         for bit in vec_bool {
@@ -374,7 +972,7 @@ mod tests {
         bit_writer.write_bitstream_fixed_huffman(&data).unwrap();
         let buffer = bit_writer.get_buffer();
         println!("{:?}", buffer);
-        let vec_bool = bit_writer.get_vec_bool();
+        let vec_bool = &bit_writer.get_vec_bool();
         // convert vec_bool to vector of 0 and 1
         let mut vec_u8: Vec<u8> = Vec::new();
         for bit in vec_bool {
@@ -398,7 +996,7 @@ mod tests {
         bit_writer.write_bitstream_fixed_huffman(&data).unwrap();
         let buffer = bit_writer.get_buffer();
         println!("{:?}", buffer);
-        let vec_bool = bit_writer.get_vec_bool();
+        let vec_bool = &bit_writer.get_vec_bool();
         // convert vec_bool to vector of 0 and 1
         let mut vec_u8: Vec<u8> = Vec::new();
         for bit in vec_bool {
@@ -422,7 +1020,7 @@ mod tests {
         bit_writer.write_bitstream_fixed_huffman(&data).unwrap();
         let buffer = bit_writer.get_buffer();
         println!("{:?}", buffer);
-        let vec_bool = bit_writer.get_vec_bool();
+        let vec_bool = &bit_writer.get_vec_bool();
         // convert vec_bool to vector of 0 and 1
         let mut vec_u8: Vec<u8> = Vec::new();
         let mut skip_count = 0;
@@ -480,6 +1078,185 @@ mod tests {
 
     }
 
+    #[test]
+    fn test_length_limited_code_lengths() {
+        // fibonacci frequencies would need 19 bits without the limit
+        let mut freqs = vec![1u32, 1];
+        for i in 2..20 {
+            freqs.push(freqs[i - 1] + freqs[i - 2]);
+        }
+        let lengths = get_length_limited_code_lengths(&freqs, 15);
+        assert!(lengths.iter().all(|&length| length >= 1 && length <= 15));
+        let kraft: f64 = lengths.iter().map(|&length| 0.5f64.powi(length as i32)).sum();
+        assert_eq!(kraft, 1.0);
+
+        assert_eq!(get_length_limited_code_lengths(&[0, 5, 0], 15), vec![0, 1, 0]);
+        assert_eq!(get_length_limited_code_lengths(&[1, 1, 2], 15), vec![2, 2, 1]);
+    }
+
+    #[test]
+    fn test_get_canonical_huffman_codes() {
+        // example from RFC 1951, 3.2.2
+        let lengths = vec![3, 3, 3, 3, 3, 2, 4, 4];
+        let codes = get_canonical_huffman_codes(&lengths);
+        assert_eq!(codes, vec![0b010, 0b011, 0b100, 0b101, 0b110, 0b00, 0b1110, 0b1111]);
+    }
+
+    #[test]
+    fn test_run_length_encode_code_lengths() {
+        let mut lengths = vec![8u8; 8];
+        lengths.extend(vec![0u8; 150]);
+        lengths.extend(vec![0u8, 0, 0, 0, 5, 5]);
+        let rle = run_length_encode_code_lengths(&lengths);
+        assert_eq!(rle, vec![(8, 0, 0), (16, 2, 3), (8, 0, 0), (18, 7, 127), (18, 7, 5), (5, 0, 0), (5, 0, 0)]);
+    }
+
+    #[test]
+    fn test_write_dynamic_huffman_empty() {
+        let mut bit_writer = BitWriter::new();
+        bit_writer.write_bitstream_dynamic_huffman(&[]).unwrap();
+        let vec_bool = &bit_writer.get_vec_bool();
+        // BFINAL=1, BTYPE=10 (lsb first)
+        assert_eq!(&vec_bool[..3], &[true, false, true]);
+        assert_eq!(bit_writer.get_buffer().len() * 8, vec_bool.len());
+    }
+
+    #[test]
+    fn test_deflate_encoder_single_block() {
+        // less than one block gives the same stream as compressing at once
+        let data = read_file_to_byte_vector(Path::new("../testdata/generic_data/cowsay/cowsay.txt")).unwrap();
+        let mut encoder = DeflateEncoder::new(Vec::new());
+        for chunk in data.chunks(7) {
+            encoder.write_all(chunk).unwrap();
+        }
+        let output = encoder.finish().unwrap();
+
+        let mut bit_writer = BitWriter::new();
+        bit_writer.write_bitstream(&data).unwrap();
+        assert_eq!(&output, bit_writer.get_buffer());
+    }
+
+    #[test]
+    fn test_deflate_encoder_blocks() {
+        let data = read_file_to_byte_vector(Path::new("../testdata/generic_data/cowsay/cowsay.txt")).unwrap();
+        let mut encoder = DeflateEncoder::with_block_size(Vec::new(), CompressionLevel::default(), 100);
+        encoder.write_all(&data).unwrap();
+        // complete blocks are written before finish
+        assert!(!encoder.writer.is_empty());
+        assert!(encoder.data.len() - encoder.history_len < 100);
+        let output = encoder.finish().unwrap();
+        // BFINAL=0, small text blocks are cheapest with fixed codes
+        assert_eq!(output[0] & 0b111, 0b010);
+    }
+
+    #[test]
+    fn test_compression_levels() {
+        let data = read_file_to_byte_vector(Path::new("../testdata/generic_data/cowsay/cowsay.txt")).unwrap();
+        let mut sizes = Vec::new();
+        for level in 0..=9 {
+            let mut bit_writer = BitWriter::with_level(CompressionLevel::new(level));
+            bit_writer.write_bitstream(&data).unwrap();
+            let buffer = bit_writer.get_buffer();
+            // BFINAL=1 and BTYPE 00 stored, 01 fixed or 10 dynamic (lsb first),
+            // dynamic codes are only tried from level 4 on
+            match level {
+                0 => assert_eq!(buffer[0] & 0b111, 0b001),
+                1..=3 => assert_eq!(buffer[0] & 0b111, 0b011),
+                _ => assert!(buffer[0] & 0b111 == 0b011 || buffer[0] & 0b111 == 0b101),
+            }
+            sizes.push(buffer.len());
+        }
+        // a stored block is the data after 5 bytes of header
+        assert_eq!(sizes[0], data.len() + 5);
+        assert!(sizes[9] < sizes[1]);
+    }
+
+    #[test]
+    fn test_write_stored_blocks() {
+        let data = vec![7u8; 70000];
+        let mut bit_writer = BitWriter::with_level(CompressionLevel::new(0));
+        bit_writer.write_bitstream(&data).unwrap();
+        let buffer = bit_writer.get_buffer();
+        assert_eq!(buffer.len(), data.len() + 10);
+        // BFINAL=0, LEN=65535 then BFINAL=1, LEN=4465
+        assert_eq!(&buffer[..5], &[0x00, 0xff, 0xff, 0x00, 0x00]);
+        assert_eq!(&buffer[65540..65545], &[0x01, 0x71, 0x11, 0x8e, 0xee]);
+    }
+
+    // the costs are exact, so they match the bits actually written
+    #[test]
+    fn test_block_costs() {
+        let data = read_file_to_byte_vector(Path::new("../testdata/generic_data/cowsay/cowsay.txt")).unwrap();
+        let mut tokens = Vec::new();
+        LZ77::with_level(WINDOW_SIZE, 258, &CompressionLevel::default()).compress(&data, &mut tokens);
+
+        let mut bit_writer = BitWriter::new();
+        bit_writer.write_fixed_block(&tokens, true).unwrap();
+        assert_eq!(bit_writer.buffer.len() as u64 * 8 + bit_writer.bit_count as u64, get_fixed_block_cost(&tokens));
+
+        let codes = DynamicCodes::from_tokens(&tokens);
+        let mut bit_writer = BitWriter::new();
+        bit_writer.write_dynamic_block_with_codes(&tokens, &codes, true).unwrap();
+        assert_eq!(bit_writer.buffer.len() as u64 * 8 + bit_writer.bit_count as u64, codes.get_block_cost(&tokens));
+
+        for offset in 0..8 {
+            let mut bit_writer = BitWriter::new();
+            bit_writer.write_single_general(0, offset, true).unwrap();
+            let cost = bit_writer.get_stored_block_cost(70000);
+            bit_writer.write_stored_block(&[0u8; 70000], true).unwrap();
+            assert_eq!(bit_writer.buffer.len() as u64 * 8 + bit_writer.bit_count as u64, offset as u64 + cost);
+        }
+    }
+
+    #[test]
+    fn test_split_into_blocks() {
+        assert_eq!(split_into_blocks(&[]), vec![0]);
+
+        // the same kind of tokens are split only at the token budget
+        let tokens = vec![Token::Literal(b'a'); 40000];
+        assert_eq!(split_into_blocks(&tokens), vec![16384, 32768, 40000]);
+
+        // text followed by binary data ends the block where the binary data starts
+        let mut tokens = Vec::new();
+        for i in 0..5120 {
+            tokens.push(Token::Literal(b"lorem ipsum dolor"[i % 17]));
+        }
+        for i in 0..5120 {
+            tokens.push(Token::Literal((i * 37) as u8 | 0x80));
+        }
+        assert_eq!(split_into_blocks(&tokens), vec![5120, 10240]);
+    }
+
+    #[test]
+    fn test_dynamic_block_chosen() {
+        let data = read_file_to_byte_vector(Path::new("../testdata/generic_data/bbrot/bbrot.pgm")).unwrap();
+        let data = &data[..20000];
+        for level in 1..=9 {
+            let mut bit_writer = BitWriter::with_level(CompressionLevel::new(level));
+            bit_writer.write_bitstream(data).unwrap();
+            let buffer = bit_writer.get_buffer();
+            // BTYPE 10 only from level 4 on
+            let expected_header = if level < 4 { 0b011 } else { 0b101 };
+            assert_eq!(buffer[0] & 0b111, expected_header);
+        }
+    }
+
+    #[test]
+    fn test_incompressible_data_is_stored() {
+        // pseudo random bytes cannot be compressed, the output is at most 5 bytes per 65535 larger
+        let mut state: u32 = 1;
+        let data: Vec<u8> = (0..100000).map(|_| {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            (state >> 16) as u8
+        }).collect();
+        let mut encoder = DeflateEncoder::new(Vec::new());
+        encoder.write_all(&data).unwrap();
+        let output = encoder.finish().unwrap();
+        assert!(output.len() <= data.len() + 5 * data.len().div_ceil(65535) + 5);
+        // BFINAL=0, BTYPE=00
+        assert_eq!(output[0] & 0b111, 0b000);
+    }
+
     #[test]
     fn test_convert_to_fixed_huffman_code_2() {
         let real_value = 'A' as u8;
@@ -1,38 +1,236 @@
-use std::collections::HashMap;
 use std::fs::File;
 use std::io::{self, Read, Write, BufReader, BufWriter};
 use std::path::Path;
-use crate::bitwriter::BitWriter;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Token {
+    Literal(u8),
+    Match(u16, u16), // (length, distance)
+}
+
+const MIN_MATCH: usize = 3;
+// the hash of a position covers its MIN_MATCH bytes, each new byte shifts the oldest one out
+const HASH_BITS: usize = 15;
+const HASH_SIZE: usize = 1 << HASH_BITS;
+const HASH_MASK: usize = HASH_SIZE - 1;
+const HASH_SHIFT: usize = HASH_BITS.div_ceil(MIN_MATCH);
+// end of a hash chain
+const NIL: usize = usize::MAX;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MatchMode {
+    Greedy, // take the longest match at each position
+    Lazy,   // emit a literal instead when the next position has a longer match
+    Lazy2,  // also look two positions ahead
+}
+
+// how hard the compressor works, the levels follow zlib's configuration table
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CompressionLevel {
+    pub store: bool,          // no compression at all
+    pub max_chain: usize,     // most positions compared for one match
+    pub good_length: usize,   // a match this long only searches a quarter of the rest of the chain
+    pub max_lazy: usize,      // a match this long is taken without looking ahead
+    pub nice_length: usize,   // a match this long ends the search
+    pub mode: MatchMode,
+    pub dynamic_blocks: bool, // dynamic huffman codes instead of the fixed ones
+}
+
+impl CompressionLevel {
+    // 0 stores, 1 is the fastest and 9 compresses best
+    pub fn new(level: u8) -> Self {
+        let (max_chain, good_length, max_lazy, nice_length, mode) = match level {
+            0 => (0, 0, 0, 0, MatchMode::Greedy),
+            1 => (4, 4, 0, 8, MatchMode::Greedy),
+            2 => (8, 4, 0, 16, MatchMode::Greedy),
+            3 => (32, 4, 0, 32, MatchMode::Greedy),
+            4 => (16, 4, 4, 16, MatchMode::Lazy),
+            5 => (32, 8, 16, 32, MatchMode::Lazy),
+            6 => (128, 8, 16, 128, MatchMode::Lazy),
+            7 => (256, 8, 32, 128, MatchMode::Lazy),
+            8 => (1024, 32, 128, 258, MatchMode::Lazy),
+            _ => (4096, 32, 258, 258, MatchMode::Lazy2),
+        };
+        CompressionLevel {
+            store: level == 0,
+            max_chain,
+            good_length,
+            max_lazy,
+            nice_length,
+            mode,
+            dynamic_blocks: level >= 4,
+        }
+    }
+}
+
+impl Default for CompressionLevel {
+    fn default() -> Self {
+        CompressionLevel::new(6)
+    }
+}
 
 pub struct LZ77 {
     window_size: usize,
     lookahead_size: usize,
-    hash_table: HashMap<Vec<u8>, Vec<usize>>,
+    max_chain: usize,
+    good_length: usize,
+    max_lazy: usize,
+    nice_length: usize,
+    mode: MatchMode,
+    head: Vec<usize>,   // most recent position of each hash
+    prev: Vec<usize>,   // previous position with the same hash, indexed by position % window_size
+    hash: usize,        // rolling hash of the next position to insert
+    inserted: usize,    // positions before this one are in the hash chains
 }
 
 impl LZ77 {
+    // searches every earlier position in the window for the longest match
     pub fn new(window_size: usize, lookahead_size: usize) -> Self {
+        LZ77::with_mode(window_size, lookahead_size, MatchMode::Greedy)
+    }
+
+    pub fn with_mode(window_size: usize, lookahead_size: usize, mode: MatchMode) -> Self {
+        LZ77::with_level(window_size, lookahead_size, &CompressionLevel {
+            store: false,
+            max_chain: window_size,
+            good_length: lookahead_size,
+            max_lazy: lookahead_size,
+            nice_length: lookahead_size,
+            mode,
+            dynamic_blocks: true,
+        })
+    }
+
+    pub fn with_level(window_size: usize, lookahead_size: usize, level: &CompressionLevel) -> Self {
         LZ77 {
             window_size,
             lookahead_size,
-            hash_table: HashMap::new(),
+            max_chain: level.max_chain.max(1),
+            good_length: level.good_length,
+            max_lazy: level.max_lazy,
+            nice_length: level.nice_length.clamp(MIN_MATCH, lookahead_size),
+            mode: level.mode,
+            head: vec![NIL; HASH_SIZE],
+            prev: vec![NIL; window_size],
+            hash: 0,
+            inserted: 0,
         }
     }
 
-    pub fn add_to_hash(&mut self, key: &[u8], position: usize) {
-        let key_vec = key.to_vec(); 
-        self.hash_table.entry(key_vec).or_default().push(position);
+    fn update_hash(hash: usize, byte: u8) -> usize {
+        ((hash << HASH_SHIFT) ^ byte as usize) & HASH_MASK
     }
 
-    pub fn update_hash(&mut self, cur_position: usize) {
-        let window_start = cur_position.saturating_sub(self.window_size); // use saturating_sub to avoid underflow
+    // positions are inserted in order, so the hash only needs the last byte of each
+    fn insert_until(&mut self, input: &[u8], end: usize) {
+        while self.inserted < end {
+            let position = self.inserted;
+            self.inserted += 1;
+            if position + MIN_MATCH > input.len() {
+                continue;
+            }
+            if position == 0 {
+                self.hash = LZ77::update_hash(LZ77::update_hash(0, input[0]), input[1]);
+            }
+            self.hash = LZ77::update_hash(self.hash, input[position + MIN_MATCH - 1]);
+            self.prev[position % self.window_size] = self.head[self.hash];
+            self.head[self.hash] = position;
+        }
+    }
+
+    fn find_match(&mut self, input: &[u8], i: usize) -> Option<(usize, usize)> {
+        self.insert_until(input, i);
+        self.longest_match(input, i)
+    }
 
-        // delete those indexes that are no longer in the window
-        for positions in self.hash_table.values_mut() {
-            positions.retain(|&pos| pos >= window_start);
+    // longest match for position i as (length, distance), the nearest one among equally long ones
+    fn longest_match(&self, input: &[u8], i: usize) -> Option<(usize, usize)> {
+        let hash = LZ77::update_hash(self.hash, input[i + MIN_MATCH - 1]);
+        let max_length = self.lookahead_size.min(input.len() - i);
+        let mut best: Option<(usize, usize)> = None;
+        let mut best_length = MIN_MATCH - 1;
+        let mut chain = self.max_chain;
+        let mut candidate = self.head[hash];
+        while candidate != NIL && i - candidate <= self.window_size && chain > 0 {
+            let mut j = 0;
+            while j < max_length && input[candidate + j] == input[i + j] {
+                j += 1;
+            }
+            if j > best_length {
+                best_length = j;
+                best = Some((j, i - candidate));
+                if j >= self.nice_length {
+                    break;
+                }
+                if j >= self.good_length {
+                    chain = chain.min(self.max_chain / 4 + 1);
+                }
+            }
+            chain -= 1;
+            candidate = self.prev[candidate % self.window_size];
         }
+        best
+    }
+
+    pub fn compress(&mut self, input: &[u8], tokens: &mut Vec<Token>) {
+        self.compress_from(input, 0, tokens)
+    }
+
+    // input[..start] was compressed before and is only searched for matches
+    pub fn compress_from(&mut self, input: &[u8], start: usize, tokens: &mut Vec<Token>) {
+        self.insert_until(input, start);
+
+        let mut i = start;
+        // the match at i when it was already found by looking ahead
+        let mut next_match: Option<Option<(usize, usize)>> = None;
+        while i < input.len() {
+            if i + MIN_MATCH > input.len() {
+                tokens.push(Token::Literal(input[i]));
+                i += 1;
+                continue;
+            }
+
+            let current_match = match next_match.take() {
+                Some(current_match) => current_match,
+                None => self.find_match(input, i),
+            };
+            let (match_length, match_distance) = match current_match {
+                Some(current_match) => current_match,
+                None => {
+                    tokens.push(Token::Literal(input[i]));
+                    i += 1;
+                    continue;
+                }
+            };
+
+            if self.mode != MatchMode::Greedy && match_length < self.max_lazy {
+                // a literal now pays off if the match after it is longer
+                if i + 1 + MIN_MATCH <= input.len() {
+                    let later_match = self.find_match(input, i + 1);
+                    if later_match.map_or(0, |(length, _)| length) > match_length {
+                        tokens.push(Token::Literal(input[i]));
+                        next_match = Some(later_match);
+                        i += 1;
+                        continue;
+                    }
+                }
+                // two literals need a match at least two bytes longer
+                if self.mode == MatchMode::Lazy2 && i + 2 + MIN_MATCH <= input.len() {
+                    let later_match = self.find_match(input, i + 2);
+                    if later_match.map_or(0, |(length, _)| length) > match_length + 1 {
+                        tokens.push(Token::Literal(input[i]));
+                        tokens.push(Token::Literal(input[i + 1]));
+                        next_match = Some(later_match);
+                        i += 2;
+                        continue;
+                    }
+                }
+            }
 
-        self.hash_table.retain(|_, v| !v.is_empty());
+            tokens.push(Token::Match(match_length as u16, match_distance as u16));
+            i += match_length;
+        }
+        self.insert_until(input, input.len());
     }
 }
 
@@ -54,86 +252,193 @@ impl OutputBuffer {
         self.buffer.extend_from_slice(formatted_pair.as_bytes());
     }
 
+    pub fn append_token(&mut self, token: &Token) {
+        match *token {
+            Token::Literal(literal) => self.append_literal(literal),
+            Token::Match(length, distance) => self.append_pair(length as usize, distance as usize),
+        }
+    }
+
     pub fn write_to_file(&self, filename: &Path) -> io::Result<()> {
         let mut file = BufWriter::new(File::create(filename)?);
         file.write_all(&self.buffer)
     }
 }
 
-impl LZ77 {
-    pub fn compress(&mut self, input: &[u8], bitwriter: &mut BitWriter) {
-        let mut i = 0;
-        while i < input.len() {
-            if i + 3 > input.len() {
-                bitwriter.write_single_literal(input[i]);
-                i += 1;
-                continue;
-            }            
-
-            let key = &input[i..i + 3];
-            if i > self.window_size {
-                self.update_hash(i);
-            }
-            // look for the key in the hash table
-            if let Some(positions) = self.hash_table.get(key) {
-                let mut match_length = 3;
-                let mut match_distance = 0;
-                for &pos in positions {
-                    let mut j = 3;
-                    while i + j < input.len() && j < self.lookahead_size {
-                        if input[i + j] != input[pos + j] {
-                            break;
-                        }
-                        j += 1;
-                    }
-                    if j >= match_length {
-                        match_length = j;
-                        match_distance = i - pos;
-                    }
-                }
+#[cfg(test)]
+mod tests {
+    use super::*;
 
+    fn read_file_to_byte_vector(file_path: &Path) -> io::Result<Vec<u8>> {
+        let mut file = BufReader::new(File::open(file_path)?);
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
+        Ok(data)
+    }
 
-                if match_length > 0 {
-                    // println!("match length: {}, match distance: {}", match_length, match_distance);
-                    bitwriter.write_length(match_length as u16);
-                    bitwriter.write_distance(match_distance as u16);
-                    for k in i..i + match_length {
-                        if k + 3 > input.len() {
-                            break;
-                        }
-                        let key = &input[k..k + 3];
-                        self.add_to_hash(key, k);
+    fn decode_tokens(tokens: &[Token]) -> Vec<u8> {
+        let mut output = Vec::new();
+        for token in tokens {
+            match *token {
+                Token::Literal(literal) => output.push(literal),
+                Token::Match(length, distance) => {
+                    for _ in 0..length {
+                        output.push(output[output.len() - distance as usize]);
                     }
+                }
+            }
+        }
+        output
+    }
 
-                    i += match_length;
-                } else { // theoretically this should never happen
-                    bitwriter.write_single_literal(input[i]);
-                    i += 1;
+    // compare every earlier position in the window, the nearest of the longest matches wins
+    fn compress_brute_force(input: &[u8], window_size: usize, lookahead_size: usize) -> Vec<Token> {
+        let mut tokens = Vec::new();
+        let mut i = 0;
+        while i < input.len() {
+            let mut best = (0, 0);
+            for pos in i.saturating_sub(window_size)..i {
+                let mut j = 0;
+                while i + j < input.len() && j < lookahead_size && input[pos + j] == input[i + j] {
+                    j += 1;
+                }
+                if j >= 3 && j >= best.0 {
+                    best = (j, i - pos);
                 }
+            }
+            if best.0 >= 3 {
+                tokens.push(Token::Match(best.0 as u16, best.1 as u16));
+                i += best.0;
             } else {
-                bitwriter.write_single_literal(input[i]);
-                self.add_to_hash(key, i);
+                tokens.push(Token::Literal(input[i]));
                 i += 1;
             }
         }
+        tokens
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn test_lz77() {
+        let mut compressor = LZ77::new(32768, 258);
+        let mut tokens = Vec::new();
+        let mut output_buffer = OutputBuffer::new();
 
-    // #[test]
-    // fn test_lz77() {
-    //     let mut compressor = LZ77::new(32768, 258);
-    //     let mut output_buffer = OutputBuffer::new();
+        let input = b"abcabcaaaaabcabcaaooooooabcabaca";
+        compressor.compress(input, &mut tokens);
+        for token in &tokens {
+            output_buffer.append_token(token);
+        }
+        let output_str = std::str::from_utf8(&output_buffer.buffer).unwrap();
+        println!("{}", output_str);
 
-    //     let input = b"abcabcaaaaabcabcaaooooooabcabaca";
-    //     compressor.compress(input, &mut output_buffer);
-    //     let output_str = std::str::from_utf8(&output_buffer.buffer).unwrap();
-    //     println!("{}", output_str);
+        // let expected_output = b"abracad<5,4>";
+        // assert_eq!(output_buffer.buffer, expected_output);
+    }
 
-    //     // let expected_output = b"abracad<5,4>";
-    //     // assert_eq!(output_buffer.buffer, expected_output);
-    // }
-}
\ No newline at end of file
+    #[test]
+    fn test_lz77_expected_outputs() {
+        for name in ["all-as-1", "all-as-2", "all-as-3", "complicated-lz77", "sliding-window-and-length"] {
+            let file_name = format!("../testdata/lz77/{}", name);
+            let input = read_file_to_byte_vector(Path::new(&file_name)).unwrap();
+            let expected = read_file_to_byte_vector(Path::new(&(file_name + ".lz77-expected"))).unwrap();
+
+            let mut tokens = Vec::new();
+            LZ77::new(32768, 258).compress(&input, &mut tokens);
+            let mut output_buffer = OutputBuffer::new();
+            for token in &tokens {
+                output_buffer.append_token(token);
+            }
+            assert_eq!(output_buffer.buffer, expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_lz77_matches_brute_force() {
+        let mut inputs: Vec<Vec<u8>> = vec![b"abcabcaaaaabcabcaaooooooabcabaca".to_vec(), vec![b'a'; 1000]];
+        inputs.push(read_file_to_byte_vector(Path::new("../testdata/generic_data/cowsay/cowsay.txt")).unwrap());
+        // pseudo random text over a small alphabet, with many hash collisions
+        let mut state: u32 = 1;
+        inputs.push((0..5000).map(|_| {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            b"abcd"[(state >> 16) as usize % 4]
+        }).collect());
+
+        for input in &inputs {
+            for &(window_size, lookahead_size) in &[(32768, 258), (100, 258), (50, 10)] {
+                let mut tokens = Vec::new();
+                LZ77::new(window_size, lookahead_size).compress(input, &mut tokens);
+                assert_eq!(tokens, compress_brute_force(input, window_size, lookahead_size));
+            }
+        }
+    }
+
+    #[test]
+    fn test_lz77_chain_limits() {
+        let input = read_file_to_byte_vector(Path::new("../testdata/generic_data/zip/zip")).unwrap();
+        for level in 1..=9 {
+            let mut tokens = Vec::new();
+            LZ77::with_level(32768, 258, &CompressionLevel::new(level)).compress(&input, &mut tokens);
+            assert_eq!(decode_tokens(&tokens), input);
+        }
+    }
+
+    #[test]
+    fn test_lz77_lazy() {
+        // greedy takes <3,3> for "abc" and then has to spell out "def" again
+        let input = b"abcxbcdefabcdef";
+        let mut tokens = Vec::new();
+        LZ77::with_mode(32768, 258, MatchMode::Lazy).compress(input, &mut tokens);
+        assert_eq!(&tokens[9..], &[Token::Literal(b'a'), Token::Match(5, 6)]);
+
+        let mut greedy_tokens = Vec::new();
+        LZ77::new(32768, 258).compress(input, &mut greedy_tokens);
+        assert_eq!(&greedy_tokens[9..], &[Token::Match(3, 9), Token::Match(3, 6)]);
+    }
+
+    #[test]
+    fn test_lz77_lazy2() {
+        // the match two bytes later is longer, the one right after is not
+        let input = b"abcxcdefgyabcdefg";
+        let mut tokens = Vec::new();
+        LZ77::with_mode(32768, 258, MatchMode::Lazy2).compress(input, &mut tokens);
+        assert_eq!(&tokens[10..], &[Token::Literal(b'a'), Token::Literal(b'b'), Token::Match(5, 8)]);
+
+        let mut lazy_tokens = Vec::new();
+        LZ77::with_mode(32768, 258, MatchMode::Lazy).compress(input, &mut lazy_tokens);
+        assert_eq!(&lazy_tokens[10..], &[Token::Match(3, 10), Token::Match(4, 8)]);
+    }
+
+    #[test]
+    fn test_lz77_lazy_modes_decode() {
+        let input = read_file_to_byte_vector(Path::new("../testdata/generic_data/zip/zip")).unwrap();
+        let mut sizes = Vec::new();
+        for mode in [MatchMode::Greedy, MatchMode::Lazy, MatchMode::Lazy2] {
+            let mut tokens = Vec::new();
+            let level = CompressionLevel { mode, ..CompressionLevel::new(6) };
+            LZ77::with_level(32768, 258, &level).compress(&input, &mut tokens);
+            assert_eq!(decode_tokens(&tokens), input);
+            sizes.push(tokens.len());
+        }
+        println!("tokens greedy {}, lazy {}, lazy2 {}", sizes[0], sizes[1], sizes[2]);
+    }
+
+    #[test]
+    fn test_lz77_compress_from() {
+        // the history is only searched, no tokens are emitted for it
+        let input = b"hello world, hello world";
+        let mut tokens = Vec::new();
+        LZ77::new(32768, 258).compress_from(input, 13, &mut tokens);
+        assert_eq!(tokens, vec![Token::Match(11, 13)]);
+    }
+
+    #[test]
+    fn test_lz77_long_run() {
+        // one chain of a million positions, nice length stops each search at the nearest one
+        let input = vec![0u8; 1 << 20];
+        let mut tokens = Vec::new();
+        LZ77::new(32768, 258).compress(&input, &mut tokens);
+        assert_eq!(tokens[0], Token::Literal(0));
+        assert_eq!(tokens[1], Token::Match(258, 1));
+        assert_eq!(decode_tokens(&tokens), input);
+    }
+}
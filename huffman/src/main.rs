@@ -3,40 +3,45 @@ mod lz77;
 
 use std::{env, fs};
 use std::fs::File;
-use std::io::{self, Read, Write, Cursor, Seek, SeekFrom};
+use std::io::{self, Read, Write, BufWriter, Cursor, Seek, SeekFrom};
 use std::path::Path;
-use crate::bitwriter::BitWriter;
-
-fn read_file_to_byte_vector(file_path: &Path) -> io::Result<Vec<u8>> {
-    let mut file = File::open(file_path)?;
-    let mut data = Vec::new();
-    file.read_to_end(&mut data)?;
-    Ok(data)
+use crate::bitwriter::DeflateEncoder;
+use crate::lz77::CompressionLevel;
+
+// "-0" to "-9" like zip
+fn parse_level(arg: &str) -> Option<u8> {
+    let digit = arg.strip_prefix('-')?;
+    if digit.len() == 1 && digit.as_bytes()[0].is_ascii_digit() {
+        Some(digit.as_bytes()[0] - b'0')
+    } else {
+        None
+    }
 }
 
 fn main() -> io::Result<()>{
-    let args: Vec<String> = env::args().collect();
+    let mut args: Vec<String> = env::args().collect();
+    let mut level = CompressionLevel::default();
+    if let Some(number) = args.get(1).and_then(|arg| parse_level(arg)) {
+        level = CompressionLevel::new(number);
+        args.remove(1);
+    }
     if args.len() != 2 {
-        eprintln!("Usage: {} <input>", args[0]);
+        eprintln!("Usage: {} [-0..-9] <input>", args[0]);
         return Err(io::Error::new(io::ErrorKind::InvalidInput, "Incorrect number of arguments"));
     }
 
     let file_path = Path::new(&args[1]);
-    let data: Vec<u8> = read_file_to_byte_vector(file_path)?;
-
-    let mut bit_writer = BitWriter::new();
+    let mut input_file = File::open(file_path)?;
 
     // add .deflate extension
     // let output_file_name: std::path::PathBuf = file_path.with_extension("deflate");
     let output_file_name = file_path.to_owned().into_os_string().into_string().unwrap() + ".deflate";
+    let output_file = File::create(Path::new(&output_file_name))?;
 
-    bit_writer.write_bitstream_fixed_huffman(&data)?;
-
-    // i haven't handle empty file yet, do I need to?
-    let mut output_file = File::create(Path::new(&output_file_name))?;
-
-    // write nothing in the file
-    output_file.write_all(&bit_writer.get_buffer())?;
+    // blocks are written to the output file as soon as they are compressed
+    let mut encoder = DeflateEncoder::with_level(BufWriter::new(output_file), level);
+    io::copy(&mut input_file, &mut encoder)?;
+    encoder.finish()?;
         
     Ok(())
 }
@@ -6,24 +6,43 @@ use std::io::{self, Read, Write, Cursor, Seek, SeekFrom};
 use std::path::Path;
 use crate::bitreader::BitReader;
 
-fn read_file_to_byte_vector(file_path: &Path) -> io::Result<Vec<u8>> {
-    let mut file = File::open(file_path)?;
-    let mut data = Vec::new();
-    file.read_to_end(&mut data)?;
-    Ok(data)
-}
+// protection against decompression bombs, like the limits of myunzip
+const DEFAULT_MAX_OUTPUT_SIZE: u64 = 16 << 30;
+// a deflate stream expands at most 1032 times, valid input never decodes past that
+const MAX_RATIO: u64 = 1032;
 
 fn main() -> io::Result<()>{
     let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        eprintln!("Usage: {} <input.deflate>", args[0]);
-        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Incorrect number of arguments"));
+    let mut max_output_size = Some(DEFAULT_MAX_OUTPUT_SIZE);
+    let mut input_path = None;
+    let mut valid_arguments = true;
+    for arg in &args[1..] {
+        match arg.as_str() {
+            "--no-limits" => max_output_size = None,
+            _ if arg.starts_with("--max-size=") => match arg["--max-size=".len()..].parse() {
+                Ok(size) => max_output_size = Some(size),
+                Err(_) => valid_arguments = false,
+            },
+            _ if !arg.starts_with('-') && input_path.is_none() => input_path = Some(arg),
+            _ => valid_arguments = false,
+        }
     }
+    let input_path = match input_path {
+        Some(input_path) if valid_arguments => input_path,
+        _ => {
+            eprintln!("Usage: {} [--max-size=BYTES] [--no-limits] <input.deflate>", args[0]);
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Incorrect arguments"));
+        }
+    };
 
-    let file_path = Path::new(&args[1]);
-    let data = read_file_to_byte_vector(file_path)?;
+    let file_path = Path::new(input_path);
+    let input_file = File::open(file_path)?;
+    let input_size = input_file.metadata()?.len();
 
-    let mut bit_reader = BitReader::new(&data);
+    let mut bit_reader = BitReader::new(input_file);
+    if let Some(max_output_size) = max_output_size {
+        bit_reader.set_output_limit(max_output_size.min(input_size.max(1).saturating_mul(MAX_RATIO)));
+    }
     // assert!(bit_reader.read_bits(1, false)? == 1);
     // bit_reader.read_bits(1, false)?;
     // let btype = bit_reader.read_bits(2, true)?;
@@ -31,11 +50,14 @@ fn main() -> io::Result<()>{
     // remove .deflate extension
     let output_file_name = file_path.file_stem().unwrap().to_str().unwrap();
 
-    let decoded_results = bit_reader.read_bitstream_blocks()?;
-        
+    // decoded blocks are written out as they come, only the sliding window stays in memory
     let mut output_file = File::create(output_file_name)?;
-    output_file.write_all(&decoded_results)?;
-
+    if let Err(e) = io::copy(&mut bit_reader, &mut output_file) {
+        // no partial output of a stream that is broken or too large
+        drop(output_file);
+        fs::remove_file(output_file_name)?;
+        return Err(e);
+    }
 
     // if btype == 1 { // fixed huffman
     //     let decoded_results = bit_reader.read_bitstream_fixed_huffman()?;
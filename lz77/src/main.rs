@@ -1,26 +1,45 @@
 mod lz77;
 
-use lz77::{LZ77, OutputBuffer};
+use lz77::{LZ77, MatchMode, OutputBuffer};
 use std::fs::File;
 use std::io::{self, Read, BufReader};
 use std::path::Path;
 
 fn main() -> io::Result<()> {
     let args: Vec<String> = std::env::args().collect();
-    if args.len() != 2 {
-        eprintln!("Usage: lz77 <input file>");
-        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Incorrect arguments"));
-    }
+    let (mode, input_file) = match args.len() {
+        2 => (MatchMode::Lazy, &args[1]),
+        3 => {
+            let mode = match args[1].as_str() {
+                "--greedy" => MatchMode::Greedy,
+                "--lazy" => MatchMode::Lazy,
+                "--lazy2" => MatchMode::Lazy2,
+                _ => {
+                    eprintln!("Usage: lz77 [--greedy|--lazy|--lazy2] <input file>");
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput, "Unknown matching mode"));
+                }
+            };
+            (mode, &args[2])
+        }
+        _ => {
+            eprintln!("Usage: lz77 [--greedy|--lazy|--lazy2] <input file>");
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Incorrect arguments"));
+        }
+    };
 
-    let input_path = Path::new(&args[1]);
+    let input_path = Path::new(input_file);
     let mut file = BufReader::new(File::open(input_path)?);
     let mut data = Vec::new();
     file.read_to_end(&mut data)?;
 
-    let mut compressor = LZ77::new(32768, 258);
+    let mut compressor = LZ77::with_mode(32768, 258, mode);
+    let mut tokens = Vec::new();
     let mut output_buffer = OutputBuffer::new();
 
-    compressor.compress(&data, &mut output_buffer);
+    compressor.compress(&data, &mut tokens);
+    for token in &tokens {
+        output_buffer.append_token(token);
+    }
 
     let output_path = input_path.with_extension("lz77");
     output_buffer.write_to_file(&output_path)
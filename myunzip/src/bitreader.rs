@@ -1,6 +1,6 @@
-use std::collections::HashMap;
 use std::hash::Hash;
-use std::{io, vec, mem};
+use std::{fmt, io, vec, mem};
+use std::sync::OnceLock;
 use std::{env, fs};
 use std::fs::File;
 use std::io::{Read, Write, Cursor, Seek, SeekFrom};
@@ -14,511 +14,796 @@ fn read_file_to_byte_vector(file_path: &Path) -> io::Result<Vec<u8>> {
 }
 
 
-pub fn check_valid_conversion(count: u8, value_from_binary: u16) -> u16 {
-    if (count == 7 && value_from_binary <= 23) {
-        return value_from_binary + 256
-    } else if (count == 8) {
-        if (value_from_binary >= 48 && value_from_binary <= 191) {
-            return value_from_binary - 48
-        }
-        else if (value_from_binary >= 192 && value_from_binary <= 199) {
-            return value_from_binary + 88
+// errors of malformed deflate data, position is the bit offset in the input where it was detected
+#[derive(Debug, Clone, PartialEq)]
+pub enum InflateError {
+    TruncatedInput { position: usize },
+    InvalidBlockType { position: usize },
+    StoredLengthMismatch { position: usize },
+    InvalidCodeLengths { position: usize },
+    InvalidSymbol { position: usize, symbol: u16 },
+    DistanceTooFarBack { position: usize, distance: usize, available: usize },
+    OutputLimitExceeded { position: usize, limit: u64 },
+    Io(io::ErrorKind),
+}
+
+impl fmt::Display for InflateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InflateError::TruncatedInput { position } =>
+                write!(f, "unexpected end of deflate data at bit {}", position),
+            InflateError::InvalidBlockType { position } =>
+                write!(f, "invalid block type at bit {}", position),
+            InflateError::StoredLengthMismatch { position } =>
+                write!(f, "stored block LEN and NLEN do not match at bit {}", position),
+            InflateError::InvalidCodeLengths { position } =>
+                write!(f, "invalid huffman code lengths at bit {}", position),
+            InflateError::InvalidSymbol { position, symbol } =>
+                write!(f, "invalid symbol {} at bit {}", symbol, position),
+            InflateError::DistanceTooFarBack { position, distance, available } =>
+                write!(f, "distance {} too far back, only {} bytes decoded at bit {}", distance, available, position),
+            InflateError::OutputLimitExceeded { position, limit } =>
+                write!(f, "output larger than the limit of {} bytes at bit {}", limit, position),
+            InflateError::Io(kind) =>
+                write!(f, "error reading deflate data: {}", kind),
         }
-        else {
-            return 300
+    }
+}
+
+impl std::error::Error for InflateError {}
+
+impl From<InflateError> for io::Error {
+    fn from(error: InflateError) -> Self {
+        match error {
+            InflateError::Io(kind) => io::Error::new(kind, error),
+            _ => io::Error::new(io::ErrorKind::InvalidData, error),
         }
-    } else if (count == 9 && value_from_binary >= 400 && value_from_binary <= 511)  {
-        return  value_from_binary - 256
-    } else {
-        return 300
     }
-    
 }
 
-pub fn get_mapping_from_canonical_huffman_lengths(list_lengths: Vec<usize>, alphabets: Vec<usize>) -> Vec<HashMap<u16, u16>>{   
-    if list_lengths.len() == 0 {
-        return Vec::new();
+// lengths of the fixed huffman literal/length codes (RFC 1951, 3.2.6)
+fn get_fixed_huffman_lengths() -> Vec<u8> {
+    let mut lengths = vec![8u8; 288];
+    for length in lengths.iter_mut().take(256).skip(144) {
+        *length = 9;
     }
+    for length in lengths.iter_mut().take(280).skip(256) {
+        *length = 7;
+    }
+    lengths
+}
 
-    let max_length = list_lengths.iter().max().unwrap();
-    let num_alphabets: usize = list_lengths.len();
-    let mut map: Vec<HashMap<u16, u16>> = vec![HashMap::new(); *max_length + 1];
-    let mut bl_count = vec![0u16; *max_length + 1];
+// fixed literal/length and distance tables, built once
+fn get_fixed_tables() -> &'static (HuffmanTable, HuffmanTable) {
+    static FIXED_TABLES: OnceLock<(HuffmanTable, HuffmanTable)> = OnceLock::new();
+    FIXED_TABLES.get_or_init(|| {
+        let hlit_table = HuffmanTable::from_lengths(&get_fixed_huffman_lengths()).unwrap();
+        let hdist_table = HuffmanTable::from_lengths(&[5u8; 32]).unwrap();
+        (hlit_table, hdist_table)
+    })
+}
 
-    // count the number of codes for each code length
-    for length in list_lengths.iter() {
-        bl_count[*length] += 1;
-    }
-    // println!("bl count is {:?}", bl_count);
+// number of code bits resolved by the first table lookup
+const PRIMARY_TABLE_BITS: u8 = 9;
+const MAX_CODE_LENGTH: usize = 15;
 
-    // find the numerical value of the smallest code for each code length
-    let mut next_codes: Vec<u16> = vec![0; *max_length + 1];
-    let mut code = 0;
-    for bits in 1..=*max_length {
-        code = (code + bl_count[bits - 1]) << 1;
-        next_codes[bits] = code;
-    }
-    // println!("next codes is {:?}", next_codes);
+// an entry is either (symbol << 16) | length for a code,
+// or (offset << 16) | SUBTABLE_FLAG | bits for a sub-table of codes longer than the primary bits,
+// 0 marks bit patterns that are not a code
+const SUBTABLE_FLAG: u32 = 0x8000;
+
+pub struct HuffmanTable {
+    primary_bits: u8,
+    entries: Vec<u32>,
+}
 
-    for i in 0..num_alphabets {
-        if list_lengths[i] == 0 {
-            continue;
+impl HuffmanTable {
+    // lengths[symbol] is the code length of symbol, 0 if unused,
+    // None if the lengths do not describe a valid prefix code
+    pub fn from_lengths(lengths: &[u8]) -> Option<HuffmanTable> {
+        let max_length = *lengths.iter().max().unwrap_or(&0) as usize;
+        if max_length > MAX_CODE_LENGTH {
+            return None;
         }
-        let length = list_lengths[i];
-        let code: u16 = next_codes[length];
-        next_codes[length] += 1;
-        map[length].insert(code, alphabets[i] as u16);
-    }
 
-    map
-}
+        // canonical codes (RFC 1951, 3.2.2)
+        let mut bl_count = [0u16; MAX_CODE_LENGTH + 1];
+        for &length in lengths {
+            bl_count[length as usize] += 1;
+        }
+        bl_count[0] = 0;
+        let mut next_codes = [0u16; MAX_CODE_LENGTH + 2];
+        let mut code: u32 = 0;
+        for bits in 1..=MAX_CODE_LENGTH {
+            code = (code + bl_count[bits - 1] as u32) << 1;
+            next_codes[bits] = code as u16;
+        }
+        // codes of each length must fit in that many bits
+        let mut available: i32 = 1;
+        for count in bl_count.iter().skip(1) {
+            available = available * 2 - *count as i32;
+            if available < 0 {
+                return None;
+            }
+        }
+
+        // codes are stored bit reversed because the stream delivers their first bit lowest
+        let mut reversed_codes = vec![0u16; lengths.len()];
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length != 0 {
+                reversed_codes[symbol] = reverse_bits(next_codes[length as usize], length);
+                next_codes[length as usize] += 1;
+            }
+        }
+
+        let primary_bits = PRIMARY_TABLE_BITS.min(max_length.max(1) as u8);
+        let primary_mask = (1usize << primary_bits) - 1;
+        let mut entries = vec![0u32; 1 << primary_bits];
+
+        // size every sub-table by the longest code sharing its primary bits
+        let mut subtable_bits = vec![0u8; 1 << primary_bits];
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length > primary_bits {
+                let index = reversed_codes[symbol] as usize & primary_mask;
+                subtable_bits[index] = subtable_bits[index].max(length - primary_bits);
+            }
+        }
+        for (index, &bits) in subtable_bits.iter().enumerate() {
+            if bits != 0 {
+                entries[index] = ((entries.len() as u32) << 16) | SUBTABLE_FLAG | bits as u32;
+                entries.resize(entries.len() + (1 << bits), 0);
+            }
+        }
 
-fn delete_zero_from_lengths_and_alphabets(list_lengths: Vec<usize>, alphabets: Vec<usize>) -> (Vec<usize>, Vec<usize>) {
-    let mut new_list_lengths: Vec<usize> = Vec::new();
-    let mut new_alphabets: Vec<usize> = Vec::new();
-    for i in 0..list_lengths.len() {
-        if list_lengths[i] != 0 {
-            new_list_lengths.push(list_lengths[i]);
-            new_alphabets.push(alphabets[i]);
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length == 0 {
+                continue;
+            }
+            let entry = ((symbol as u32) << 16) | length as u32;
+            let reversed_code = reversed_codes[symbol] as usize;
+            if length <= primary_bits {
+                // every index whose low bits are this code
+                let mut index = reversed_code;
+                while index < 1 << primary_bits {
+                    entries[index] = entry;
+                    index += 1 << length;
+                }
+            } else {
+                let pointer = entries[reversed_code & primary_mask];
+                let offset = (pointer >> 16) as usize;
+                let bits = (pointer & 0xF) as u8;
+                let mut index = reversed_code >> primary_bits;
+                while index < 1 << bits {
+                    entries[offset + index] = entry;
+                    index += 1 << (length - primary_bits);
+                }
+            }
         }
+
+        Some(HuffmanTable { primary_bits, entries })
     }
-    (new_list_lengths, new_alphabets)
-}
 
-fn generate_no_zero_lengths_and_alphabets(list_lengths: Vec<usize>) -> (Vec<usize>, Vec<usize>) {
-    let mut new_list_lengths: Vec<usize> = Vec::new();
-    let mut new_alphabets: Vec<usize> = Vec::new();
-    for i in 0..list_lengths.len() {
-        if list_lengths[i] != 0 {
-            new_list_lengths.push(list_lengths[i]);
-            new_alphabets.push(i);
+    // the entry of the code in the lowest bits, 0 if they are not a code
+    #[inline]
+    fn lookup(&self, bits: u64) -> u32 {
+        let entry = self.entries[bits as usize & ((1 << self.primary_bits) - 1)];
+        if entry & SUBTABLE_FLAG == 0 {
+            return entry;
         }
+        let offset = (entry >> 16) as usize;
+        let subtable_bits = entry & 0xF;
+        self.entries[offset + ((bits >> self.primary_bits) as usize & ((1 << subtable_bits) - 1))]
     }
-    (new_list_lengths, new_alphabets)
 }
 
-pub struct BitReader<'a> {
-    data: &'a [u8],
-    position: usize,
-    vec_bool: Vec<bool>,
-    resulted_bytes: Vec<u8>,
+// base values and extra bits of length codes 257..285 and distance codes 0..29 (RFC 1951, 3.2.5)
+const LENGTH_BASE: [u16; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31,
+    35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+const LENGTH_EXTRA_BITS: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2,
+    3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DISTANCE_BASE: [u16; 30] = [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193,
+    257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577];
+const DISTANCE_EXTRA_BITS: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6,
+    7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+// the most bits one literal/length code and its distance take: codes of 15 bits,
+// 5 extra bits of the length and 13 of the distance
+const MAX_SYMBOL_BITS: u32 = 48;
+
+// reverse the order of the lowest count bits of value
+fn reverse_bits(value: u16, count: u8) -> u16 {
+    if count == 0 {
+        return 0
+    }
+    value.reverse_bits() >> (16 - count)
 }
 
-impl<'a> BitReader<'a> {
-    pub fn new(data: &'a [u8]) -> Self {
-        let mut bitreader = BitReader{ data, position: 0, vec_bool: Vec::new(), resulted_bytes: Vec::new()};
-        bitreader.get_vec_bool();
-        bitreader
-    }
-
-    pub fn read_bits(&mut self, count: u8, reverse: bool) -> io::Result<u16> {
-        // access vec_bool to get bit
-        let mut result: u16 = 0;
-        let count = count as usize;
-        for i in 0..count {
-            let cur_position = self.position + i;
-            let cur_bit = self.vec_bool[cur_position] as u16;
-            if reverse {
-                result += cur_bit << i; // shift right
-            } else {
-            result += cur_bit << (count - i - 1); // shift left
+// bytes read from the input source at a time; the buffer starts small for short
+// streams and grows while reads fill it
+const MIN_INPUT_BUFFER_SIZE: usize = 1 << 10;
+const INPUT_BUFFER_SIZE: usize = 1 << 16;
+// back references reach at most this far
+const WINDOW_SIZE: usize = 32768;
+// read decodes until this many bytes are waiting
+const OUTPUT_CHUNK_SIZE: usize = 1 << 16;
+
+// where decoding resumes on the next read
+enum BlockState {
+    Header,
+    Stored { remaining: usize },
+    Fixed,
+    Dynamic { hlit_table: HuffmanTable, hdist_table: HuffmanTable },
+    Done,
+    Failed(InflateError),
+}
+
+pub struct BitReader<R: Read> {
+    input: R,
+    input_buffer: Vec<u8>,
+    input_start: usize,   // next byte of input_buffer to load into bit_buffer
+    input_end: usize,     // end of the bytes read into input_buffer
+    input_eof: bool,
+    bytes_loaded: usize,  // input bytes moved into bit_buffer or copied out so far
+    bit_buffer: u64,      // next bit of the stream is the lowest bit
+    bit_count: u32,       // number of valid bits in bit_buffer
+    window: Vec<u8>,      // decoded bytes, at least WINDOW_SIZE of them kept before output_position
+    output_position: usize, // next byte of window returned by read
+    output_discarded: u64,  // decoded bytes dropped from the start of window
+    output_limit: u64,      // decoding more bytes than this is an error
+    state: BlockState,
+    last_block: bool,
+}
+
+impl<R: Read> BitReader<R> {
+    pub fn new(input: R) -> Self {
+        BitReader {
+            input,
+            input_buffer: vec![0; MIN_INPUT_BUFFER_SIZE],
+            input_start: 0,
+            input_end: 0,
+            input_eof: false,
+            bytes_loaded: 0,
+            bit_buffer: 0,
+            bit_count: 0,
+            window: Vec::new(),
+            output_position: 0,
+            output_discarded: 0,
+            output_limit: u64::MAX,
+            state: BlockState::Header,
+            last_block: false,
+        }
+    }
+
+    // stop with OutputLimitExceeded once the stream decodes to more than limit bytes
+    pub fn set_output_limit(&mut self, limit: u64) {
+        self.output_limit = limit;
+    }
+
+    // number of bits consumed so far
+    pub fn position(&self) -> usize {
+        self.bytes_loaded * 8 - self.bit_count as usize
+    }
+
+    // read the next chunk of the input, false at its end
+    fn fill_input_buffer(&mut self) -> Result<bool, InflateError> {
+        while !self.input_eof {
+            match self.input.read(&mut self.input_buffer) {
+                Ok(0) => self.input_eof = true,
+                Ok(count) => {
+                    if count == self.input_buffer.len() && count < INPUT_BUFFER_SIZE {
+                        let new_size = 2 * count;
+                        self.input_buffer.resize(new_size, 0);
+                    }
+                    self.input_start = 0;
+                    self.input_end = count;
+                    return Ok(true);
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(InflateError::Io(e.kind())),
             }
         }
-        self.position += count;
-        Ok(result)
+        Ok(false)
     }
 
+    // load whole bytes until the buffer holds at least 56 bits or the input ends
+    #[inline]
+    fn refill(&mut self) -> Result<(), InflateError> {
+        // as many whole bytes as fit, with a single load if 8 of them are buffered
+        if self.bit_count <= 55 && self.input_end - self.input_start >= 8 {
+            let count = (63 - self.bit_count as usize) / 8;
+            let bytes = &self.input_buffer[self.input_start..self.input_start + 8];
+            let value = u64::from_le_bytes(bytes.try_into().unwrap()) & ((1u64 << (count * 8)) - 1);
+            self.bit_buffer |= value << self.bit_count;
+            self.input_start += count;
+            self.bytes_loaded += count;
+            self.bit_count += count as u32 * 8;
+            return Ok(());
+        }
+        self.refill_bytes()
+    }
 
-    pub fn match_fixed_huffman(&mut self) -> io::Result<u16> {
-        
-        let mut result: u16 = 0;
-        let counts: Vec<u8> = vec![7, 8, 9];
-        for count in counts {
-            result = self.read_bits(count, false)?;
-            let result_conversed = check_valid_conversion(count, result);
-            if result_conversed == 300 { // magic number for: not matched, try next
-                self.position -= count as usize;
-            } 
-            else {
-                result = result_conversed;
+    // byte by byte near the end of the input buffer
+    #[cold]
+    fn refill_bytes(&mut self) -> Result<(), InflateError> {
+        while self.bit_count <= 56 {
+            if self.input_start == self.input_end && !self.fill_input_buffer()? {
                 break;
             }
+            self.bit_buffer |= (self.input_buffer[self.input_start] as u64) << self.bit_count;
+            self.input_start += 1;
+            self.bytes_loaded += 1;
+            self.bit_count += 8;
         }
-
-        Ok(result)
+        Ok(())
     }
 
-    // for debug
-    fn print_bitstream_desired_range(&mut self, start: usize, end: usize) {
-        for i in start..end {
-            print!("{}", if self.vec_bool[i] {1} else {0});
+    // the next count bits lsb first, zero filled past the end of the input
+    fn peek_bits(&mut self, count: u8) -> Result<u16, InflateError> {
+        if self.bit_count < count as u32 {
+            self.refill()?;
         }
-        println!();
+        Ok((self.bit_buffer & ((1u64 << count) - 1)) as u16)
     }
 
-    // to put a static table outside ?
-    fn read_length(&mut self, code: u16) -> u16 {
-        assert!(code > 256);
-        let mut designation: u16 = 0;
-        if code < 265 {
-            return code - 254
+    fn consume_bits(&mut self, count: u8) -> Result<(), InflateError> {
+        if self.bit_count < count as u32 {
+            self.refill()?;
+            if self.bit_count < count as u32 {
+                return Err(InflateError::TruncatedInput { position: self.position() });
+            }
         }
-        else if code < 269 {
-            designation = self.read_bits(1, true).unwrap();
-            return 2 * (code - 265) + 11 + designation
+        self.bit_buffer >>= count;
+        self.bit_count -= count as u32;
+        Ok(())
+    }
+
+    // reverse = true reads the value lsb first (extra bits, header fields),
+    // reverse = false reads it msb first (huffman codes)
+    pub fn read_bits(&mut self, count: u8, reverse: bool) -> Result<u16, InflateError> {
+        let result = self.peek_bits(count)?;
+        self.consume_bits(count)?;
+        if reverse {
+            Ok(result)
+        } else {
+            Ok(reverse_bits(result, count))
         }
-        else if code < 273 {
-            designation = self.read_bits(2, true).unwrap();
-            return 4 * (code - 269) + 19 + designation
+    }
+
+    // skip the remaining bits of the current byte
+    fn align_to_byte(&mut self) {
+        let skip = self.bit_count % 8;
+        self.bit_buffer >>= skip;
+        self.bit_count -= skip;
+    }
+
+    fn build_table(&self, lengths: &[u8]) -> Result<HuffmanTable, InflateError> {
+        HuffmanTable::from_lengths(lengths).ok_or(InflateError::InvalidCodeLengths { position: self.position() })
+    }
+
+    pub fn match_fixed_huffman(&mut self) -> Result<u16, InflateError> {
+        let (hlit_table, _) = get_fixed_tables();
+        self.decode_one_dynamic_huffman(hlit_table)
+    }
+
+    // length of length codes 257..285 with their extra bits
+    fn read_length(&mut self, code: u16) -> Result<u16, InflateError> {
+        if !(257..=285).contains(&code) {
+            return Err(InflateError::InvalidSymbol { position: self.position(), symbol: code });
         }
-        else if code < 277 {
-            designation = self.read_bits(3, true).unwrap();
-            return 8 * (code - 273) + 35 + designation
+        let index = code as usize - 257;
+        Ok(LENGTH_BASE[index] + self.read_bits(LENGTH_EXTRA_BITS[index], true)?)
+    }
+
+    fn read_distance(&mut self, length_code: u16) -> Result<u16, InflateError> {
+        if length_code >= 30 {
+            return Err(InflateError::InvalidSymbol { position: self.position(), symbol: length_code });
         }
-        else if code < 281 {
-            designation = self.read_bits(4, true).unwrap();
-            return 16 * (code - 277) + 67 + designation
+        let index = length_code as usize;
+        Ok(DISTANCE_BASE[index] + self.read_bits(DISTANCE_EXTRA_BITS[index], true)?)
+    }
+
+    // the lowest count bits of the buffer, which must hold them
+    #[inline]
+    fn take_bits(&mut self, count: u32) -> u64 {
+        let bits = self.bit_buffer & ((1u64 << count) - 1);
+        self.bit_buffer >>= count;
+        self.bit_count -= count;
+        bits
+    }
+
+    // copy len bytes starting distance bytes back, the ranges may overlap
+    fn copy_match(&mut self, len: usize, distance: usize) -> Result<(), InflateError> {
+        if distance > self.window.len() {
+            return Err(InflateError::DistanceTooFarBack {
+                position: self.position(),
+                distance,
+                available: self.window.len(),
+            });
         }
-        else if code < 285 {
-            designation = self.read_bits(5, true).unwrap();
-            return 32 * (code - 281) + 131 + designation
+        // runs of one byte, and copies that do not overlap, take a single call
+        let start = self.window.len() - distance;
+        if distance == 1 {
+            let byte = self.window[start];
+            self.window.resize(self.window.len() + len, byte);
+            return Ok(());
         }
-        else {
-            assert!(code == 285);
-            return 258
+        // the copied bytes repeat with period distance, so copy from start in growing chunks
+        let mut remaining = len;
+        while remaining > 0 {
+            let chunk = remaining.min(self.window.len() - start);
+            self.window.extend_from_within(start..(start + chunk));
+            remaining -= chunk;
         }
+        Ok(())
     }
 
-    fn read_distance(&mut self, length_code: u16) -> u16 {
-        if length_code < 4 {
-            return length_code + 1
-        }
-        let extra_bits =  ((length_code - 2) / 2 ) as u32 ;
-        let to_add = self.read_bits(extra_bits as u8, true).unwrap();
-        if length_code % 2 == 0 {
-            return 2u16.pow(extra_bits + 1) + 1 + to_add
+    fn next_block_state(&self) -> BlockState {
+        if self.last_block {
+            BlockState::Done
         } else {
-            return 2u16.pow(extra_bits + 1) + 1 + to_add + 2u16.pow(extra_bits)
+            BlockState::Header
         }
     }
 
-    // read one block 
-    fn read_one_block(&mut self) -> io::Result<()> {
-        let bfinal = self.read_bits(1, false)?;
-        let fill_the_end_to_multiple_8 = bfinal == 1;
+    fn read_block_header(&mut self) -> Result<BlockState, InflateError> {
+        self.last_block = self.read_bits(1, false)? == 1;
 
+        let btype_position = self.position();
         let btype = self.read_bits(2, true)?;
-        if btype == 1 { // fixed huffman
-            self.read_fixed_block(fill_the_end_to_multiple_8)?
+        if btype == 0 { // stored (no compression)
+            self.read_stored_header()
+        } else if btype == 1 { // fixed huffman
+            Ok(BlockState::Fixed)
+        } else if btype == 2 { // dynamic huffman
+            self.read_dynamic_header()
         } else {
-            self.read_dynamic_block(fill_the_end_to_multiple_8)?
+            Err(InflateError::InvalidBlockType { position: btype_position })
         }
+    }
 
-        Ok(())
+    fn read_stored_header(&mut self) -> Result<BlockState, InflateError> {
+        self.align_to_byte();
+
+        // LEN and NLEN are little endian, so reading them lsb first gives the value directly
+        let len = self.read_bits(16, true)?;
+        let nlen = self.read_bits(16, true)?;
+        if len != !nlen {
+            return Err(InflateError::StoredLengthMismatch { position: self.position() - 32 });
+        }
+        Ok(BlockState::Stored { remaining: len as usize })
     }
 
-    fn read_fixed_block(&mut self, bfinal: bool) -> io::Result<()> {
-        let mut cur_len: usize = 0;
-        while self.position < self.data.len() * 8{
-            let next_code = self.match_fixed_huffman()?;
+    // copy up to count bytes of a stored block to the window, returns how many were copied
+    fn copy_stored_bytes(&mut self, count: usize) -> Result<usize, InflateError> {
+        // whole bytes still in the bit buffer come first
+        let mut copied = 0;
+        while copied < count && self.bit_count >= 8 {
+            self.window.push(self.bit_buffer as u8);
+            self.bit_buffer >>= 8;
+            self.bit_count -= 8;
+            copied += 1;
+        }
+        if copied == count {
+            return Ok(copied);
+        }
 
-            assert!(next_code <= 285);
-            if next_code == 256 { // EOB
-                break;
+        if self.input_start == self.input_end && !self.fill_input_buffer()? {
+            if copied == 0 {
+                return Err(InflateError::TruncatedInput { position: self.position() });
             }
-            else if next_code > 256 {
-                let len = self.read_length(next_code) as usize;
-                let distance_code = self.read_bits(5, false).unwrap();
-                let distance = self.read_distance(distance_code) as usize;
-
-        
-                let start = self.resulted_bytes.len() - distance;
-                if len <= distance {
-                    let repeat_sequence = self.resulted_bytes[start..(start + len)].to_vec();
-                    self.resulted_bytes.extend(&repeat_sequence);
-                } else {
-                    for i in 0..len {
-                        let index = i + start;
-                        self.resulted_bytes.push(self.resulted_bytes[index]);
-                    }
-                }
-                cur_len += len;
-            }
-            else  
-            {
-                self.resulted_bytes.push(next_code as u8);
-                cur_len += 1;
-            }
-        }
-        if ! bfinal {
-            self.read_one_block()
-        } else {
-            Ok(()) // last block
+            return Ok(copied);
         }
+        let from_input = (count - copied).min(self.input_end - self.input_start);
+        self.window.extend_from_slice(&self.input_buffer[self.input_start..self.input_start + from_input]);
+        self.input_start += from_input;
+        self.bytes_loaded += from_input;
+        Ok(copied + from_input)
     }
 
-    fn read_dynamic_block(&mut self, bfinal: bool) -> io::Result<()> {
-        let (hlit, hdist, hclen) = self.parse_dynamic_header()?;
-        let (key_vector, value_vector) = self.read_hclen(hclen)?;
-        let hclen_map = get_mapping_from_canonical_huffman_lengths(value_vector, key_vector);
-        let hlit_map = self.get_hlit_or_hdist_map(hlit, &hclen_map)?;
-        let hdist_map = self.get_hlit_or_hdist_map(hdist, &hclen_map)?;
+    // literals and <length, distance> pairs until the end of block code or until limit bytes are waiting,
+    // returns whether the block ended
+    fn read_compressed_data(&mut self, hlit_table: &HuffmanTable, hdist_table: &HuffmanTable, limit: usize) -> Result<bool, InflateError> {
+        while self.window.len() - self.output_position < limit {
+            if self.bit_count < MAX_SYMBOL_BITS {
+                self.refill()?;
+            }
+            // the buffer holds a whole symbol, decode it without checking for the end of the input;
+            // codes that are not in the table are left to the checked path for their error
+            if self.bit_count >= MAX_SYMBOL_BITS {
+                let entry = hlit_table.lookup(self.bit_buffer);
+                if entry & 0xF != 0 {
+                    self.take_bits(entry & 0xF);
+                    let symbol = (entry >> 16) as u16;
+                    if symbol < 256 {
+                        self.window.push(symbol as u8);
+                        // a second literal often fits in what is left
+                        if self.bit_count >= MAX_CODE_LENGTH as u32 && self.window.len() - self.output_position < limit {
+                            let entry = hlit_table.lookup(self.bit_buffer);
+                            if entry & 0xF != 0 && entry >> 16 < 256 {
+                                self.take_bits(entry & 0xF);
+                                self.window.push((entry >> 16) as u8);
+                            }
+                        }
+                        continue;
+                    }
+                    if symbol == 256 { // EOB
+                        return Ok(true);
+                    }
+                    if symbol > 285 {
+                        return Err(InflateError::InvalidSymbol { position: self.position(), symbol });
+                    }
+                    let index = symbol as usize - 257;
+                    let len = LENGTH_BASE[index] as usize + self.take_bits(LENGTH_EXTRA_BITS[index] as u32) as usize;
+                    let entry = hdist_table.lookup(self.bit_buffer);
+                    if entry & 0xF != 0 && entry >> 16 < 30 {
+                        self.take_bits(entry & 0xF);
+                        let index = (entry >> 16) as usize;
+                        let distance = DISTANCE_BASE[index] as usize
+                            + self.take_bits(DISTANCE_EXTRA_BITS[index] as u32) as usize;
+                        self.copy_match(len, distance)?;
+                    } else {
+                        let distance_code = self.decode_one_dynamic_huffman(hdist_table)?;
+                        let distance = self.read_distance(distance_code)? as usize;
+                        self.copy_match(len, distance)?;
+                    }
+                    continue;
+                }
+            }
 
-        let mut cur_len: usize = 0;
-        while self.position < self.data.len() * 8{
-            // println!("cur_len is {}", cur_len);
-            let next_code = self.decode_one_dynamic_huffman(&hlit_map)?;
+            let next_code = self.decode_one_dynamic_huffman(hlit_table)?;
 
-            assert!(next_code <= 285);
             if next_code == 256 { // EOB
-                break;
+                return Ok(true);
             }
             else if next_code > 256 {
-                let len = self.read_length(next_code) as usize;
-                // println!("len is {}", len);
-
-                let distance_code = self.decode_one_dynamic_huffman(&hdist_map)?;
-
-                let distance = self.read_distance(distance_code) as usize;
-
-                // assert!(cur_len >= distance); 
-
-        
-                let start = self.resulted_bytes.len() - distance;
-                if len <= distance {
-                    let repeat_sequence = self.resulted_bytes[start..(start + len)].to_vec();
-                    self.resulted_bytes.extend(&repeat_sequence);
-                } else {
-                    for i in 0..len {
-                        let index = i + start;
-                        self.resulted_bytes.push(self.resulted_bytes[index]);
-                    }
-                }
-                cur_len += len;
+                let len = self.read_length(next_code)? as usize;
+                let distance_code = self.decode_one_dynamic_huffman(hdist_table)?;
+                let distance = self.read_distance(distance_code)? as usize;
+                self.copy_match(len, distance)?;
             }
             else  
             {
-                self.resulted_bytes.push(next_code as u8);
-                cur_len += 1;
+                self.window.push(next_code as u8);
             }
         }
-        if ! bfinal {
-            self.read_one_block()
-        } else {
-            Ok(()) // last block
-        }
+        Ok(false)
     }
 
-    pub fn read_bitstream_blocks(&mut self) -> io::Result<Vec<u8>> {
-        self.read_one_block();
-        // replace self.resulted_bytes with a new empty vector, return the original vector
-        Ok(mem::take(&mut self.resulted_bytes)) 
+    fn read_dynamic_header(&mut self) -> Result<BlockState, InflateError> {
+        let (hlit, hdist, hclen) = self.parse_dynamic_header()?;
+        let hclen_lengths = self.read_hclen(hclen)?;
+        let hclen_map = self.build_table(&hclen_lengths)?;
+        let hlit_table = self.get_hlit_or_hdist_map(hlit, &hclen_map)?;
+        let hdist_table = self.get_hlit_or_hdist_map(hdist, &hclen_map)?;
+        Ok(BlockState::Dynamic { hlit_table, hdist_table })
     }
-    
-    // deprecated
-    pub fn read_bitstream_fixed_huffman(&mut self) -> io::Result<Vec<u8>> {
-        let mut result: Vec<u8> = Vec::new();
-        let mut cur_len: usize = 0;
-
-        while self.position < self.data.len() * 8{
-            // println!("{}", self.position);
-            let next_code = self.match_fixed_huffman()?;
 
-            assert!(next_code <= 285);
-            if next_code == 256 { // EOB
-                break;
-            }
-            else if next_code > 256 {
-                let len = self.read_length(next_code) as usize;
-                let distance_code = self.read_bits(5, false).unwrap();
-                let distance = self.read_distance(distance_code) as usize;
-
-                // if cur_len < distance {
-                //     println!("self.position: {}", self.position);
-                //     println!("len {} , cur_len: {}, distance: {}",len, cur_len, distance);
-                // }
-                assert!(cur_len >= distance); 
-                // if debug {
-                //     println!("len {} , cur_len: {}, distance: {}",len, cur_len, distance);
-                //     debug = false;
-                // }
-        
-                let start = cur_len - distance;
-                if len <= distance {
-                    let repeat_sequence = result[start..(start + len)].to_vec();
-                    result.extend(&repeat_sequence);
-                } else {
-                    // println!("len: {}, distance: {}", len, distance);
-                    // let repeat_time = len / distance;
-                    // let repeat_sequence = result[(cur_len - distance)..(cur_len)].to_vec();
-                    // for _ in 0..repeat_time {
-                    //     result.extend(repeat_sequence.clone());
-                    // }
-                    for i in 0..len {
-                        let index = i % distance + start;
-                        result.push(result[index]);
+    // decode until at least limit bytes are waiting in the window or the last block ended
+    fn decode(&mut self, limit: usize) -> Result<(), InflateError> {
+        while self.window.len() - self.output_position < limit {
+            self.state = match mem::replace(&mut self.state, BlockState::Done) {
+                BlockState::Header => self.read_block_header()?,
+                BlockState::Stored { remaining } => {
+                    let wanted = remaining.min(limit - (self.window.len() - self.output_position));
+                    let copied = self.copy_stored_bytes(wanted)?;
+                    if copied == remaining {
+                        self.next_block_state()
+                    } else {
+                        BlockState::Stored { remaining: remaining - copied }
                     }
                 }
-                cur_len += len;
-            }
-            else  
-            {
-                result.push(next_code as u8);
-                cur_len += 1;
-            }
+                BlockState::Fixed => {
+                    let (hlit_table, hdist_table) = get_fixed_tables();
+                    if self.read_compressed_data(hlit_table, hdist_table, limit)? {
+                        self.next_block_state()
+                    } else {
+                        BlockState::Fixed
+                    }
+                }
+                BlockState::Dynamic { hlit_table, hdist_table } => {
+                    if self.read_compressed_data(&hlit_table, &hdist_table, limit)? {
+                        self.next_block_state()
+                    } else {
+                        BlockState::Dynamic { hlit_table, hdist_table }
+                    }
+                }
+                BlockState::Done => {
+                    self.state = BlockState::Done;
+                    return Ok(());
+                }
+                BlockState::Failed(error) => {
+                    self.state = BlockState::Failed(error.clone());
+                    return Err(error);
+                }
+            };
         }
-        Ok(result)
+        Ok(())
     }
 
-    // read the whole data
-    pub fn get_vec_bool(&mut self) {
-        let size: usize = self.data.len() * 8;
-        let mut result: Vec<bool> = Vec::with_capacity(size);
-        for i in 0..size {
-            let byte_index = i / 8;
-            let bit_index = i % 8;
-            let bit = (self.data[byte_index] >> bit_index) & 1;
-            result.push(bit != 0);
+    // like decode, but a failure is returned again by every later call
+    fn fill(&mut self, limit: usize) -> Result<(), InflateError> {
+        // decode at most one byte more than allowed to notice the limit
+        let decoded = self.output_discarded + self.window.len() as u64;
+        let allowed = self.output_limit.saturating_sub(decoded).saturating_add(1);
+        let mut result = self.decode(limit.min(usize::try_from(allowed).unwrap_or(usize::MAX)));
+        if result.is_ok() && self.output_discarded + self.window.len() as u64 > self.output_limit {
+            result = Err(InflateError::OutputLimitExceeded { position: self.position(), limit: self.output_limit });
         }
-        self.vec_bool = result;
+        if let Err(error) = &result {
+            self.state = BlockState::Failed(error.clone());
+        }
+        result
+    }
+
+    // drop decoded bytes that were read and are too far back to be referenced
+    fn discard_history(&mut self) {
+        if self.output_position >= 2 * WINDOW_SIZE {
+            let discard = self.output_position - WINDOW_SIZE;
+            self.window.drain(..discard);
+            self.output_position -= discard;
+            self.output_discarded += discard as u64;
+        }
+    }
+
+    // decode all remaining blocks at once
+    pub fn read_bitstream_blocks(&mut self) -> Result<Vec<u8>, InflateError> {
+        self.fill(usize::MAX)?;
+        // nothing can be decoded after the last block, so the history goes too
+        self.output_discarded += self.window.len() as u64;
+        let mut output = mem::take(&mut self.window);
+        output.drain(..self.output_position);
+        self.output_position = 0;
+        Ok(output)
+    }
+    
+    // deprecated: decodes the data of one fixed huffman block whose header was already read
+    pub fn read_bitstream_fixed_huffman(&mut self) -> Result<Vec<u8>, InflateError> {
+        self.state = BlockState::Fixed;
+        self.last_block = true;
+        self.read_bitstream_blocks()
     }
 
     // dynamic huffman code
 
-    fn parse_dynamic_header(&mut self) -> io::Result<(usize, usize, usize)> {
+    fn parse_dynamic_header(&mut self) -> Result<(usize, usize, usize), InflateError> {
         let hlit = self.read_bits(5, true)? as usize + 257;  // number of literal/length codes
         let hdist = self.read_bits(5, true)? as usize + 1;   // number of distance codes
         let hclen = self.read_bits(4, true)? as usize + 4;   // number of code length codes
+        if hlit > 286 || hdist > 30 {
+            return Err(InflateError::InvalidCodeLengths { position: self.position() - 14 });
+        }
     
         Ok((hlit, hdist, hclen))
     }
 
-    fn read_hclen(&mut self, hclen: usize) -> io::Result<(Vec<usize>, Vec<usize>)> {
-        let mut code_length_map = HashMap::new();
+    // code length code lengths indexed by alphabet
+    fn read_hclen(&mut self, hclen: usize) -> Result<Vec<u8>, InflateError> {
         let order = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+        let mut code_lengths = vec![0u8; 19];
+        for &alphabet in order.iter().take(hclen) {
+            code_lengths[alphabet] = self.read_bits(3, true)? as u8;
+        }
+        Ok(code_lengths)
+    }
 
-        for i in 0..hclen {
-            let code = self.read_bits(3, true)?;
-            if code == 0 {
-                continue;
-            }
-            code_length_map.insert(order[i], code);
-        }
-        
-        // key_vector is alphabet, value_vector is code length
-        let mut key_vector: Vec<usize> = Vec::new();
-        let mut value_vector: Vec<usize> = Vec::new();
-        for i in 0..19 {
-            if let Some(&value) = code_length_map.get(&i) {
-                if value != 0 {
-                    key_vector.push(i);
-                    value_vector.push(value as usize);
-                }
+    fn decode_one_dynamic_huffman(&mut self, table: &HuffmanTable) -> Result<u16, InflateError> {
+        let bits = self.peek_bits(MAX_CODE_LENGTH as u8)? as usize;
+        let mut entry = table.entries[bits & ((1 << table.primary_bits) - 1)];
+        if entry & SUBTABLE_FLAG != 0 {
+            let offset = (entry >> 16) as usize;
+            let subtable_bits = entry & 0xF;
+            entry = table.entries[offset + ((bits >> table.primary_bits) & ((1 << subtable_bits) - 1))];
+        }
+        let length = (entry & 0xF) as u8;
+        if length == 0 {
+            // past the end of the input the missing bits read as zeros
+            if (self.bit_count as usize) < MAX_CODE_LENGTH {
+                return Err(InflateError::TruncatedInput { position: self.position() });
             }
+            return Err(InflateError::InvalidSymbol { position: self.position(), symbol: bits as u16 });
         }
-        Ok((key_vector, value_vector))
-
+        self.consume_bits(length)?;
+        Ok((entry >> 16) as u16)
     }
 
-    fn decode_one_dynamic_huffman(&mut self, map: &Vec<HashMap<u16, u16>>) -> io::Result<u16> {
-        let mut code: u16 = 0;
-        let mut count: u8 = 1;
-        loop {
-            let next_huffman_code = self.read_bits(count, false)?;
-            // try to find the code in the map
-            if let Some(&value) = map[count as usize].get(&next_huffman_code) {
-                code = value;
-                break;
-            }
-            self.position -= count as usize;
-            count += 1;
-        }
-        // println!("code is {} at length {}", code, count);
-        Ok(code)
-    }
-
-    // fn decode_hclen_huffman(&mut self, hclen_map: &Vec<HashMap<u16, u16>>) -> io::Result<Vec<usize>> {
-    //     let mut code_lengths: Vec<usize> = vec![0; 19];
-    //     for i in 0..19 {
-    //         let code = self.decode_one_dynamic_huffman()?;
-    //         code_lengths[i] = code as usize;
-    //     }
-    //     Ok(code_lengths)
-    // }
-
-    fn read_and_decode_dynamic_huffman(&mut self, num_huffman_codes: usize, map: &Vec<HashMap<u16, u16>>) -> io::Result<(Vec<usize>, Vec<usize>)> {
-        let mut code_lengths: Vec<usize> = Vec::new();
-        let mut alphabets: Vec<usize> = Vec::new();
-        let mut cur_alpha = 0;
-        let mut i = 0;
-        let mut last_code = 0;
-        while i < num_huffman_codes {
-            let code = self.decode_one_dynamic_huffman(map)?;
-            if code < 16 {
-                if code != 0 {
-                    code_lengths.push(code as usize);
-                    alphabets.push(cur_alpha);
-                }
-                last_code = code;
-                cur_alpha += 1;
-                i += 1;
-                
+    // code lengths of num_huffman_codes alphabets, run length encoded with 16, 17 and 18
+    fn read_and_decode_dynamic_huffman(&mut self, num_huffman_codes: usize, table: &HuffmanTable) -> Result<Vec<u8>, InflateError> {
+        let mut code_lengths: Vec<u8> = Vec::with_capacity(num_huffman_codes);
+        while code_lengths.len() < num_huffman_codes {
+            let code_position = self.position();
+            let code = self.decode_one_dynamic_huffman(table)?;
+            let (length, repeat) = if code < 16 {
+                (code as u8, 1)
             } else if code == 16 {
-                let repeat = self.read_bits(2, true)? as usize;
-                if last_code != 0 {
-                    for _ in 0..(repeat + 3) {
-                        code_lengths.push(last_code as usize);
-                        alphabets.push(cur_alpha);
-                        cur_alpha += 1;
-                    }
-                }
-                i += repeat + 3;
+                let last_code = match code_lengths.last() {
+                    Some(&last_code) => last_code,
+                    None => return Err(InflateError::InvalidCodeLengths { position: code_position }),
+                };
+                (last_code, self.read_bits(2, true)? as usize + 3)
             } else if code == 17 {
-                let repeat = self.read_bits(3, true)? as usize;
-                i += repeat + 3;
-                cur_alpha += repeat + 3;
-                last_code = 0;
-            } else if code == 18 {
-                let repeat = self.read_bits(7, true)? as usize;
-                i += repeat + 11;
-                cur_alpha += repeat + 11;
-                last_code = 0;
+                (0, self.read_bits(3, true)? as usize + 3)
+            } else {
+                (0, self.read_bits(7, true)? as usize + 11)
+            };
+            if code_lengths.len() + repeat > num_huffman_codes {
+                return Err(InflateError::InvalidCodeLengths { position: code_position });
             }
+            code_lengths.resize(code_lengths.len() + repeat, length);
         }
-
-        // println!("list {:?}", code_lengths);
-        // println!("alphabets {:?}", alphabets);
-        Ok((code_lengths, alphabets))
+        Ok(code_lengths)
     }
 
 
-    fn get_hlit_or_hdist_map(&mut self, hlit: usize, hclen_map: &Vec<HashMap<u16, u16>>) -> io::Result<Vec<HashMap<u16, u16>>> {
-        let (list_lengths, alphabets) = self.read_and_decode_dynamic_huffman(hlit, hclen_map)?;
-        let map = get_mapping_from_canonical_huffman_lengths(list_lengths, alphabets);
-        Ok(map)
+    fn get_hlit_or_hdist_map(&mut self, hlit: usize, hclen_map: &HuffmanTable) -> Result<HuffmanTable, InflateError> {
+        let list_lengths = self.read_and_decode_dynamic_huffman(hlit, hclen_map)?;
+        // a literal/length code without end of block could never finish the block
+        if hlit >= 257 && list_lengths[256] == 0 {
+            return Err(InflateError::InvalidCodeLengths { position: self.position() });
+        }
+        self.build_table(&list_lengths)
     }
-
-    // fn get_hdist_map(&mut self, hdist: usize, hclen_map: &Vec<HashMap<u16, u16>>) -> io::Result<Vec<HashMap<u16, u16>>> {
-    //     let code_lengths = self.read_and_decode_dynamic_huffman(hdist, hclen_map)?;
-    //     let map = get_mapping_from_canonical_huffman_lengths(code_lengths);
-    //     Ok(map)
-    // }
-
-
-
+}
 
 
+impl<R: Read> Read for BitReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.output_position == self.window.len() {
+            self.discard_history();
+            self.fill(OUTPUT_CHUNK_SIZE)?;
+        }
+        let waiting = &self.window[self.output_position..];
+        let count = waiting.len().min(buf.len());
+        buf[..count].copy_from_slice(&waiting[..count]);
+        self.output_position += count;
+        Ok(count)
+    }
 }
 
-
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    fn setup_bitreader<'a>(data: &'a [u8]) -> BitReader<'a> {
+    fn setup_bitreader(data: &[u8]) -> BitReader<&[u8]> {
         BitReader::new(data)
     }
 
+    // raw deflate data of the first entry of a zip file
+    fn get_first_entry_deflate_data(zip_data: &[u8]) -> &[u8] {
+        let compressed_size = u32::from_le_bytes(zip_data[18..22].try_into().unwrap()) as usize;
+        let file_name_length = u16::from_le_bytes(zip_data[26..28].try_into().unwrap()) as usize;
+        let extra_field_length = u16::from_le_bytes(zip_data[28..30].try_into().unwrap()) as usize;
+        let start = 30 + file_name_length + extra_field_length;
+        &zip_data[start..start + compressed_size]
+    }
+
+    // run with: cargo test --release -p inflate bench -- --ignored --nocapture
+    // MB/s on a single core VM      cowsay  bbrot  zip  fixed-distances  fixed-lengths
+    //   refill per peek, per byte       65    157   67               61           1840
+    //   refill per symbol, u64 loads   150    450  140              150           3000
+    //   zlib through python            128    311  115
+    #[test]
+    #[ignore]
+    fn bench_read_bitstream_blocks() {
+        let inputs = [("../testdata/generic_data/cowsay/cowsay.zip", true),
+                      ("../testdata/generic_data/bbrot/bbrot.zip", true),
+                      ("../testdata/generic_data/zip/zip.zip", true),
+                      ("../testdata/inflate/fixed-distances-stress.deflate", false),
+                      ("../testdata/inflate/fixed-lengths-stress.deflate", false)];
+        for (file_name, is_zip) in inputs.iter() {
+            let file_data = read_file_to_byte_vector(Path::new(file_name)).unwrap();
+            let data = if *is_zip { get_first_entry_deflate_data(&file_data) } else { &file_data[..] };
+
+            // small inputs are decoded often enough to time them
+            let start = std::time::Instant::now();
+            let mut total_bytes = 0;
+            while total_bytes < 200_000_000 {
+                let mut bit_reader = BitReader::new(data);
+                total_bytes += bit_reader.read_bitstream_blocks().unwrap().len();
+            }
+            let seconds = start.elapsed().as_secs_f64();
+            println!("{}: {:.2} MB/s", file_name, total_bytes as f64 / seconds / 1e6);
+        }
+    }
+
     #[test]
     fn test_read_bits_1() {
         match env::current_dir() {
@@ -530,7 +815,7 @@ mod tests {
         let data = read_file_to_byte_vector(file_path).unwrap();
         println!("{:?}", data);
 
-        let mut bit_reader = BitReader::new(&data);
+        let mut bit_reader = BitReader::new(&data[..]);
         // bit_reader.print_bitstream();
         bit_reader.read_bits(1, false);
         println!("{}", bit_reader.read_bits(2, false).unwrap());
@@ -544,7 +829,7 @@ mod tests {
         let file_path = Path::new(file_name);
         let data = read_file_to_byte_vector(file_path).unwrap();
 
-        let mut bit_reader = BitReader::new(&data);
+        let mut bit_reader = BitReader::new(&data[..]);
         bit_reader.read_bits(3, false);
         let decoded_results = bit_reader.read_bitstream_fixed_huffman().unwrap();
         println!("{:?}", decoded_results);
@@ -555,7 +840,7 @@ mod tests {
         let file_name = "../testdata/inflate/fixed-lengths-stress.deflate";
         let file_path = Path::new(file_name);
         let data = read_file_to_byte_vector(file_path).unwrap();
-        let mut bit_reader = BitReader::new(&data);
+        let mut bit_reader = BitReader::new(&data[..]);
         bit_reader.read_bits(3, false);
         let decoded_results = bit_reader.read_bitstream_fixed_huffman().unwrap();
         println!("{:?}", decoded_results);
@@ -566,7 +851,7 @@ mod tests {
         let file_name = "../testdata/inflate/dynamic-huffman-empty.deflate";
         let file_path = Path::new(file_name);
         let data = read_file_to_byte_vector(file_path).unwrap();
-        let mut bit_reader = BitReader::new(&data);
+        let mut bit_reader = BitReader::new(&data[..]);
         let decoded_results = bit_reader.read_bitstream_blocks().unwrap();
         println!("{:?}", decoded_results);
     }
@@ -576,7 +861,7 @@ mod tests {
         let file_name = "../testdata/inflate/dynamic-huffman-empty-no-distance-code.deflate";
         let file_path = Path::new(file_name);
         let data = read_file_to_byte_vector(file_path).unwrap();
-        let mut bit_reader = BitReader::new(&data);
+        let mut bit_reader = BitReader::new(&data[..]);
         let decoded_results = bit_reader.read_bitstream_blocks().unwrap();
         println!("{:?}", decoded_results);
     }
@@ -586,7 +871,7 @@ mod tests {
         let file_name = "../testdata/inflate/dynamic-huffman-one-distance-code.deflate";
         let file_path = Path::new(file_name);
         let data = read_file_to_byte_vector(file_path).unwrap();
-        let mut bit_reader = BitReader::new(&data);
+        let mut bit_reader = BitReader::new(&data[..]);
         let decoded_results = bit_reader.read_bitstream_blocks().unwrap();
         println!("decoded result is {:?}", decoded_results);
     }
@@ -596,13 +881,160 @@ mod tests {
         let file_name = "../bbrot.pgm.deflate";
         let file_path = Path::new(file_name);
         let data = read_file_to_byte_vector(file_path).unwrap();
-        let mut bit_reader = BitReader::new(&data);
+        let mut bit_reader = BitReader::new(&data[..]);
         let decoded_results = bit_reader.read_bitstream_blocks().unwrap();
         println!("decoded result is {:?}", decoded_results);
     }
 
     
 
+    #[test]
+    fn test_stored_block() {
+        // BFINAL=1 BTYPE=00, padding, LEN=5, NLEN=!5, "hello"
+        let data = [0x01, 0x05, 0x00, 0xfa, 0xff, b'h', b'e', b'l', b'l', b'o'];
+        let mut bit_reader = BitReader::new(&data[..]);
+        let decoded_results = bit_reader.read_bitstream_blocks().unwrap();
+        assert_eq!(decoded_results, b"hello");
+    }
+
+    #[test]
+    fn test_stored_block_followed_by_fixed_block() {
+        // BFINAL=0 BTYPE=00 "ab", then fixed-huffman-overlapping-run0
+        let mut data = vec![0x00, 0x02, 0x00, 0xfd, 0xff, b'a', b'b'];
+        data.extend_from_slice(&read_file_to_byte_vector(Path::new("../testdata/inflate/fixed-huffman-overlapping-run0.deflate")).unwrap());
+        let mut bit_reader = BitReader::new(&data[..]);
+        let decoded_results = bit_reader.read_bitstream_blocks().unwrap();
+        assert_eq!(decoded_results, [b'a', b'b', 1, 1, 1, 1, 1]);
+    }
+
+    #[test]
+    fn test_stored_block_bad_nlen() {
+        let data = [0x01, 0x05, 0x00, 0x00, 0x00, b'h', b'e', b'l', b'l', b'o'];
+        let mut bit_reader = BitReader::new(&data[..]);
+        assert_eq!(bit_reader.read_bitstream_blocks(), Err(InflateError::StoredLengthMismatch { position: 8 }));
+    }
+
+    #[test]
+    fn test_reserved_block_type() {
+        let data = [0x07, 0x00];
+        let mut bit_reader = BitReader::new(&data[..]);
+        assert_eq!(bit_reader.read_bitstream_blocks(), Err(InflateError::InvalidBlockType { position: 1 }));
+    }
+
+    #[test]
+    fn test_truncated_input() {
+        // stored block shorter than LEN
+        let data = [0x01, 0x05, 0x00, 0xfa, 0xff, b'h', b'e'];
+        let mut bit_reader = BitReader::new(&data[..]);
+        assert_eq!(bit_reader.read_bitstream_blocks(), Err(InflateError::TruncatedInput { position: 56 }));
+
+        // every prefix of a fixed huffman stream misses its end of block code
+        let data = read_file_to_byte_vector(Path::new("../testdata/inflate/fixed-huffman-overlapping-run0.deflate")).unwrap();
+        for end in 0..data.len() - 1 {
+            let mut bit_reader = BitReader::new(&data[..end]);
+            assert!(matches!(bit_reader.read_bitstream_blocks(), Err(InflateError::TruncatedInput { .. })));
+        }
+    }
+
+    #[test]
+    fn test_distance_too_far_back() {
+        // fixed block starting with length 3 (code 257) at distance 1 (code 0)
+        let data = [0x03, 0x02, 0x00, 0x00];
+        let mut bit_reader = BitReader::new(&data[..]);
+        assert_eq!(bit_reader.read_bitstream_blocks(),
+                   Err(InflateError::DistanceTooFarBack { position: 15, distance: 1, available: 0 }));
+    }
+
+    #[test]
+    fn test_invalid_symbol() {
+        // fixed block with literal/length code 286
+        let data = [0x1b, 0x03, 0x00];
+        let mut bit_reader = BitReader::new(&data[..]);
+        assert_eq!(bit_reader.read_bitstream_blocks(), Err(InflateError::InvalidSymbol { position: 11, symbol: 286 }));
+    }
+
+    #[test]
+    fn test_buffered_symbol_errors() {
+        // the same streams followed by enough bytes to decode from a full bit buffer
+        let mut data = vec![0x03, 0x02, 0x00, 0x00];
+        data.resize(16, 0);
+        assert_eq!(BitReader::new(&data[..]).read_bitstream_blocks(),
+                   Err(InflateError::DistanceTooFarBack { position: 15, distance: 1, available: 0 }));
+        let mut data = vec![0x1b, 0x03, 0x00];
+        data.resize(16, 0);
+        assert_eq!(BitReader::new(&data[..]).read_bitstream_blocks(),
+                   Err(InflateError::InvalidSymbol { position: 11, symbol: 286 }));
+    }
+
+    #[test]
+    fn test_invalid_code_lengths() {
+        // dynamic block whose code length code lengths are all 1
+        let data = [0x05, 0xe0, 0x93, 0x24, 0x49, 0x92, 0x24, 0x49, 0x92, 0x00];
+        let mut bit_reader = BitReader::new(&data[..]);
+        assert_eq!(bit_reader.read_bitstream_blocks(), Err(InflateError::InvalidCodeLengths { position: 17 + 19 * 3 }));
+    }
+
+    #[test]
+    fn test_streaming_read() {
+        // small input reads and small output reads give the same bytes as decoding at once
+        let inputs = [("../testdata/generic_data/zip/zip.zip", "../testdata/generic_data/zip/zip"),
+                      ("../testdata/generic_data/bbrot/bbrot.zip", "../testdata/generic_data/bbrot/bbrot.pgm"),
+                      ("../testdata/inflate/fixed-distances-stress.deflate", "../testdata/inflate/fixed-distances-stress-expected")];
+        for (file_name, expected_file_name) in inputs.iter() {
+            let file_data = read_file_to_byte_vector(Path::new(file_name)).unwrap();
+            let data = if file_name.ends_with(".zip") { get_first_entry_deflate_data(&file_data) } else { &file_data[..] };
+            let expected = read_file_to_byte_vector(Path::new(expected_file_name)).unwrap();
+
+            let mut bit_reader = BitReader::new(io::BufReader::with_capacity(3, data));
+            let mut decoded_results = Vec::new();
+            let mut chunk = [0u8; 1000];
+            loop {
+                let count = bit_reader.read(&mut chunk).unwrap();
+                if count == 0 {
+                    break;
+                }
+                decoded_results.extend_from_slice(&chunk[..count]);
+            }
+            assert_eq!(decoded_results, expected);
+            assert!(bit_reader.window.len() <= 2 * WINDOW_SIZE + OUTPUT_CHUNK_SIZE + 258);
+        }
+    }
+
+    #[test]
+    fn test_output_limit() {
+        let data = read_file_to_byte_vector(Path::new("../testdata/inflate/fixed-distances-stress.deflate")).unwrap();
+        let expected = read_file_to_byte_vector(Path::new("../testdata/inflate/fixed-distances-stress-expected")).unwrap();
+
+        // exactly the decoded size is allowed
+        let mut bit_reader = BitReader::new(&data[..]);
+        bit_reader.set_output_limit(expected.len() as u64);
+        assert_eq!(bit_reader.read_bitstream_blocks().unwrap(), expected);
+
+        let mut bit_reader = BitReader::new(&data[..]);
+        bit_reader.set_output_limit(expected.len() as u64 - 1);
+        assert!(matches!(bit_reader.read_bitstream_blocks(),
+            Err(InflateError::OutputLimitExceeded { limit, .. }) if limit == expected.len() as u64 - 1));
+
+        // reading stops without buffering much more than the limit
+        let mut bit_reader = BitReader::new(&data[..]);
+        bit_reader.set_output_limit(1000);
+        let mut output = Vec::new();
+        let error = bit_reader.read_to_end(&mut output).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(output.len() <= 1000 + 258);
+    }
+
+    #[test]
+    fn test_streaming_read_error() {
+        let data = [0x07, 0x00];
+        let mut bit_reader = BitReader::new(&data[..]);
+        let mut decoded_results = Vec::new();
+        let error = bit_reader.read_to_end(&mut decoded_results).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        // the failure is sticky
+        assert!(bit_reader.read(&mut [0u8; 16]).is_err());
+    }
+
     #[test]
     fn test_parse_dynamic_header() {
         let file_names = ["../testdata/inflate/dynamic-huffman-one-distance-code.deflate",
@@ -611,55 +1043,83 @@ mod tests {
         for file_name in file_names.iter() {
             let file_path = Path::new(file_name);
             let data = read_file_to_byte_vector(file_path).unwrap();
-            let mut bit_reader = BitReader::new(&data);
+            let mut bit_reader = BitReader::new(&data[..]);
             bit_reader.read_bits(3, false);
             let (hlit, hdist, hclen) = BitReader::parse_dynamic_header(&mut bit_reader).unwrap();
             println!("hlit: {}, hdist: {}, hclen: {}", hlit, hdist, hclen);
         }
     }
 
+    // decode every symbol of a table from its canonical code written msb first
+    fn check_huffman_table(lengths: &[u8], expected_codes: &[(u16, u8, u16)]) {
+        let table = HuffmanTable::from_lengths(lengths).unwrap();
+        for &(code, length, symbol) in expected_codes {
+            // the code followed by ones, lsb first
+            let bits = reverse_bits(code, length) as u32 | (0xFFFF << length);
+            let data = [bits as u8, (bits >> 8) as u8, 0xFF];
+            let mut bit_reader = BitReader::new(&data[..]);
+            assert_eq!(bit_reader.decode_one_dynamic_huffman(&table).unwrap(), symbol);
+            assert_eq!(bit_reader.position(), length as usize);
+        }
+    }
+
     #[test]
     fn test_get_mapping_from_canonical_huffman_lengths() {
-        let list_lengths = vec![3, 3, 3, 3, 3, 2, 4, 4];
-        let alphabets = vec![0, 1, 2, 3, 4, 5, 6, 7];
-        let result = get_mapping_from_canonical_huffman_lengths(list_lengths, alphabets);
-        println!("{:?}", result);
+        // example from RFC 1951, 3.2.2
+        let lengths = vec![3, 3, 3, 3, 3, 2, 4, 4];
+        check_huffman_table(&lengths, &[(0b010, 3, 0), (0b011, 3, 1), (0b100, 3, 2), (0b101, 3, 3),
+                                        (0b110, 3, 4), (0b00, 2, 5), (0b1110, 4, 6), (0b1111, 4, 7)]);
     }
 
     // I used dynamic-huffman-one-distance-code as test case
     #[test]
     fn test_get_mapping_from_canonical_huffman_lengths_2() {
-        let list_lengths = vec![2; 4];
-        let alphabets = vec![0, 1, 2, 18];
-        let result = get_mapping_from_canonical_huffman_lengths(list_lengths, alphabets);
-        println!("{:?}", result);
-        
-        let list_lengths = vec![2, 1, 2];
-        let alphabets = vec![1, 256, 257];
-        let result = get_mapping_from_canonical_huffman_lengths(list_lengths, alphabets);
-        println!("hlit map is {:?}", result);
-
-        let list_lengths = vec![1];
-        let alphabets = vec![0];
-        let result = get_mapping_from_canonical_huffman_lengths(list_lengths, alphabets);
-        println!("hlit map is {:?}", result);
+        let mut lengths = vec![0u8; 19];
+        for alphabet in [0, 1, 2, 18] {
+            lengths[alphabet] = 2;
+        }
+        check_huffman_table(&lengths, &[(0b00, 2, 0), (0b01, 2, 1), (0b10, 2, 2), (0b11, 2, 18)]);
+
+        let mut lengths = vec![0u8; 258];
+        lengths[1] = 2;
+        lengths[256] = 1;
+        lengths[257] = 2;
+        check_huffman_table(&lengths, &[(0b10, 2, 1), (0b0, 1, 256), (0b11, 2, 257)]);
+
+        check_huffman_table(&[1], &[(0b0, 1, 0)]);
+    }
+
+    #[test]
+    fn test_huffman_table_long_codes() {
+        // codes of length 1 to 15 use the sub-tables
+        let mut lengths: Vec<u8> = (1..=15).collect();
+        lengths.push(15);
+        let mut expected_codes = Vec::new();
+        for (symbol, &length) in lengths.iter().enumerate() {
+            // length - 1 ones and a zero, the last code is all ones
+            let code = if symbol == 15 { 0x7FFF } else { (1u16 << length) - 2 };
+            expected_codes.push((code, length, symbol as u16));
+        }
+        check_huffman_table(&lengths, &expected_codes);
+    }
+
+    #[test]
+    fn test_huffman_table_over_subscribed() {
+        assert!(HuffmanTable::from_lengths(&[1, 1, 1]).is_none());
+    }
+
+    #[test]
+    fn test_fixed_huffman_table() {
+        let lengths = get_fixed_huffman_lengths();
+        check_huffman_table(&lengths, &[(48, 8, 0), (191, 8, 143), (400, 9, 144), (511, 9, 255),
+                                        (0, 7, 256), (23, 7, 279), (192, 8, 280), (199, 8, 287)]);
     }
 
     #[test]
     fn test_dynamic_huffman_empty() {
-        let list_lengths = vec![1, 1];
-        let alphabets = vec![1,18];
-        let map = get_mapping_from_canonical_huffman_lengths(list_lengths, alphabets);
-        println!("{:?}", map);
-
-        // let list_lengths = vec![1, 1];
-        // let alphabets = vec![0, 256];
-        // let result = get_mapping_from_canonical_huffman_lengths(list_lengths, alphabets);
-        // println!("hlit map is {:?}", result);
-
-        // let list_lengths = vec![1];
-        // let alphabets = vec![0];
-        // let result = get_mapping_from_canonical_huffman_lengths(list_lengths, alphabets);
-        // println!("hlit map is {:?}", result);
-    }
-}
\ No newline at end of file
+        let mut lengths = vec![0u8; 19];
+        lengths[1] = 1;
+        lengths[18] = 1;
+        check_huffman_table(&lengths, &[(0b0, 1, 1), (0b1, 1, 18)]);
+    }
+}
@@ -0,0 +1,152 @@
+use std::fmt;
+use std::io;
+
+const DEFAULT_MAX_TOTAL_SIZE: u64 = 16 << 30;
+// a deflate stream expands at most 1032 times (258 bytes for each 2 bit match),
+// anything beyond that claims more data than it can hold
+const DEFAULT_MAX_RATIO: u64 = 1032;
+const DEFAULT_MAX_ENTRIES: u64 = 100_000;
+const DEFAULT_MAX_DEPTH: usize = 64;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LimitError {
+    TooManyEntries { count: u64, limit: u64 },
+    TooDeep { file_name: String, depth: usize, limit: usize },
+    RatioTooHigh { file_name: String, uncompressed_size: u64, compressed_size: u64, limit: u64 },
+    TotalSizeTooLarge { file_name: String, total_size: u64, limit: u64 },
+}
+
+impl fmt::Display for LimitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LimitError::TooManyEntries { count, limit } =>
+                write!(f, "archive has {} entries, more than the limit of {}", count, limit),
+            LimitError::TooDeep { file_name, depth, limit } =>
+                write!(f, "{} is nested {} levels deep, more than the limit of {}", file_name, depth, limit),
+            LimitError::RatioTooHigh { file_name, uncompressed_size, compressed_size, limit } =>
+                write!(f, "{} expands from {} to {} bytes, more than the limit of {} times",
+                    file_name, compressed_size, uncompressed_size, limit),
+            LimitError::TotalSizeTooLarge { file_name, total_size, limit } =>
+                write!(f, "extracting {} makes {} bytes in total, more than the limit of {}",
+                    file_name, total_size, limit),
+        }
+    }
+}
+
+impl std::error::Error for LimitError {}
+
+impl From<LimitError> for io::Error {
+    fn from(error: LimitError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, error)
+    }
+}
+
+// protection against decompression bombs; the sizes in the central directory are
+// checked up front, and the inflater stops at the uncompressed size it claims
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    pub max_total_size: u64, // bytes of all entries together
+    pub max_ratio: u64,      // uncompressed size of an entry per compressed byte
+    pub max_entries: u64,
+    pub max_depth: usize,    // directory nesting of entry names
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_total_size: DEFAULT_MAX_TOTAL_SIZE,
+            max_ratio: DEFAULT_MAX_RATIO,
+            max_entries: DEFAULT_MAX_ENTRIES,
+            max_depth: DEFAULT_MAX_DEPTH,
+        }
+    }
+}
+
+impl Limits {
+    pub fn unlimited() -> Self {
+        Limits {
+            max_total_size: u64::MAX,
+            max_ratio: u64::MAX,
+            max_entries: u64::MAX,
+            max_depth: usize::MAX,
+        }
+    }
+
+    pub fn check_entry_count(&self, count: u64) -> Result<(), LimitError> {
+        if count > self.max_entries {
+            return Err(LimitError::TooManyEntries { count, limit: self.max_entries });
+        }
+        Ok(())
+    }
+
+    // total_size is what the entries before this one decompressed to
+    pub fn check_entry(&self, file_name: &str, compressed_size: u64, uncompressed_size: u64, total_size: u64)
+        -> Result<(), LimitError> {
+        let depth = file_name.split('/').filter(|name| !name.is_empty() && *name != ".").count();
+        if depth > self.max_depth {
+            return Err(LimitError::TooDeep { file_name: file_name.to_string(), depth, limit: self.max_depth });
+        }
+        // entries of a few bytes may not even hold the deflate overhead
+        if uncompressed_size > compressed_size.max(1).saturating_mul(self.max_ratio) {
+            return Err(LimitError::RatioTooHigh {
+                file_name: file_name.to_string(),
+                uncompressed_size,
+                compressed_size,
+                limit: self.max_ratio,
+            });
+        }
+        let total_size = total_size.saturating_add(uncompressed_size);
+        if total_size > self.max_total_size {
+            return Err(LimitError::TotalSizeTooLarge {
+                file_name: file_name.to_string(),
+                total_size,
+                limit: self.max_total_size,
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entry_count() {
+        let limits = Limits { max_entries: 2, ..Limits::default() };
+        assert!(limits.check_entry_count(2).is_ok());
+        assert_eq!(limits.check_entry_count(3), Err(LimitError::TooManyEntries { count: 3, limit: 2 }));
+        assert!(Limits::unlimited().check_entry_count(u64::MAX).is_ok());
+    }
+
+    #[test]
+    fn test_depth() {
+        let limits = Limits { max_depth: 2, ..Limits::default() };
+        assert!(limits.check_entry("a/b", 10, 10, 0).is_ok());
+        assert!(limits.check_entry("a/./b//", 10, 10, 0).is_ok());
+        assert!(matches!(limits.check_entry("a/b/c", 10, 10, 0), Err(LimitError::TooDeep { depth: 3, .. })));
+    }
+
+    #[test]
+    fn test_ratio() {
+        let limits = Limits { max_ratio: 100, ..Limits::default() };
+        assert!(limits.check_entry("a", 10, 1000, 0).is_ok());
+        assert!(limits.check_entry("a", 0, 100, 0).is_ok());
+        assert!(matches!(limits.check_entry("a", 10, 1001, 0), Err(LimitError::RatioTooHigh { .. })));
+        // 10 bytes of deflate data claiming 1 TiB
+        assert!(Limits::default().check_entry("a", 10, 1 << 40, 0).is_err());
+        assert!(Limits::unlimited().check_entry("a", 10, 1 << 40, 0).is_ok());
+        // 20 MiB of zeros compressed by zip -9
+        assert!(Limits::default().check_entry("zeros", 20362, 20 << 20, 0).is_ok());
+    }
+
+    #[test]
+    fn test_total_size() {
+        let limits = Limits { max_total_size: 1000, ..Limits::default() };
+        assert!(limits.check_entry("a", 600, 600, 400).is_ok());
+        assert_eq!(limits.check_entry("a", 600, 600, 401),
+            Err(LimitError::TotalSizeTooLarge { file_name: "a".to_string(), total_size: 1001, limit: 1000 }));
+        let error: io::Error = limits.check_entry("a", 600, 600, 401).unwrap_err().into();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -1,10 +1,23 @@
 mod bitreader;
+mod crc32;
+mod limits;
+mod names;
+mod paths;
+mod timestamps;
+mod zip64;
 
 use std::{env, fs};
 use std::fs::File;
 use std::io::{self, Read, Write, Cursor, Seek, SeekFrom};
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
 use crate::bitreader::BitReader;
+use crate::crc32::Crc32;
+use crate::limits::Limits;
+use crate::names::{decode_cp437, decode_entry_name, decode_text};
+use crate::paths::{check_link_target, check_no_symlinks, sanitize_entry_name};
+use crate::timestamps::{dos_date_time_to_unix, read_extended_timestamp_mtime, unix_to_system_time};
+use crate::zip64::{read_zip64_end_of_central_directory, read_zip64_extra_field, read_zip64_locator};
 
 fn read_u16_le(cursor: &mut Cursor<&[u8]>) -> io::Result<u16> {
     let mut bytes: [u8; 2] = [0u8; 2];
@@ -18,89 +31,734 @@ fn read_u32_le(cursor: &mut Cursor<&[u8]>) -> io::Result<u32> {
     Ok(u32::from_le_bytes(bytes))
 }
 
+fn read_u64_le(cursor: &mut Cursor<&[u8]>) -> io::Result<u64> {
+    let mut bytes = [0u8; 8];
+    cursor.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
 fn find_eocd(buffer: &[u8]) -> Option<usize> {
     let eocd_signature: [u8; 4] = [0x50, 0x4B, 0x05, 0x06];
     buffer.windows(4).rposition(|window| window == eocd_signature)
 }
 
-fn main() -> io::Result<()> {
-    let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        eprintln!("Usage: {} <input.zip>", args[0]);
-        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Incorrect number of arguments"));
+fn verify_crc32(file_name: &str, expected_crc: u32, actual_crc: u32) -> io::Result<()> {
+    if actual_crc != expected_crc {
+        return Err(io::Error::new(io::ErrorKind::InvalidData,
+            format!("CRC-32 mismatch for {}: expected {:08x}, got {:08x}", file_name, expected_crc, actual_crc)));
     }
+    Ok(())
+}
 
-    let zip_file_path = &args[1];
-    let mut zip_file = File::open(zip_file_path)?;
-    let mut buffer = Vec::new();
+struct CentralDirectoryEntry {
+    version_made_by: u16,
+    version_needed: u16,
+    flags: u16,
+    compression_method: u16,
+    last_mod_time: u16,
+    last_mod_date: u16,
+    crc: u32,
+    compressed_size: u64,
+    uncompressed_size: u64,
+    external_attributes: u32,
+    local_header_offset: u64,
+    file_name: String,
+    extra_field: Vec<u8>,
+    comment: String,
+}
 
-    zip_file.read_to_end(&mut buffer)?;
-    let mut cursor = Cursor::new(&buffer[..]);
+// the header ids of all fields in an extra field
+fn get_extra_field_ids(extra_field: &[u8]) -> Vec<u16> {
+    let mut ids = Vec::new();
+    let mut position = 0;
+    while position + 4 <= extra_field.len() {
+        ids.push(u16::from_le_bytes([extra_field[position], extra_field[position + 1]]));
+        let data_size = u16::from_le_bytes([extra_field[position + 2], extra_field[position + 3]]) as usize;
+        position += 4 + data_size;
+    }
+    ids
+}
 
-    let signature = read_u32_le(&mut cursor)?;
-    if signature != 0x04034b50 {
-        return Err(io::Error::new(io::ErrorKind::InvalidData, "Not a Zip File"));
+fn read_central_directory_entry(cursor: &mut Cursor<&[u8]>) -> io::Result<CentralDirectoryEntry> {
+    let signature = read_u32_le(cursor)?;
+    if signature != 0x02014b50 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Bad central directory signature"));
     }
 
+    let version_made_by = read_u16_le(cursor)?;
+    let version_needed = read_u16_le(cursor)?;
+    let flags = read_u16_le(cursor)?;
+    let compression_method = read_u16_le(cursor)?;
+    let last_mod_time = read_u16_le(cursor)?;
+    let last_mod_date = read_u16_le(cursor)?;
+    let crc = read_u32_le(cursor)?;
+    let compressed_size = read_u32_le(cursor)? as u64;
+    let uncompressed_size = read_u32_le(cursor)? as u64;
+    let file_name_length = read_u16_le(cursor)?;
+    let extra_field_length = read_u16_le(cursor)?;
+    let file_comment_length = read_u16_le(cursor)?;
+    // disk number start, internal attributes
     cursor.seek(SeekFrom::Current(4))?;
-    let compression_method = read_u16_le(&mut cursor)?;
-    assert!(compression_method == 0 || compression_method == 8);
+    let external_attributes = read_u32_le(cursor)?;
+    let local_header_offset = read_u32_le(cursor)? as u64;
 
-    cursor.seek(SeekFrom::Current(16))?;
+    let mut file_name_bytes = vec![0; file_name_length as usize];
+    cursor.read_exact(&mut file_name_bytes)?;
+    let mut extra_field = vec![0; extra_field_length as usize];
+    cursor.read_exact(&mut extra_field)?;
+    let file_name = decode_entry_name(&file_name_bytes, flags, version_made_by >> 8, &extra_field);
+    let mut comment_bytes = vec![0; file_comment_length as usize];
+    cursor.read_exact(&mut comment_bytes)?;
 
+    let mut entry = CentralDirectoryEntry {
+        version_made_by,
+        version_needed,
+        flags,
+        compression_method,
+        last_mod_time,
+        last_mod_date,
+        crc,
+        compressed_size,
+        uncompressed_size,
+        external_attributes,
+        local_header_offset,
+        file_name,
+        extra_field: Vec::new(),
+        comment: decode_text(&comment_bytes, flags, version_made_by >> 8),
+    };
+    read_zip64_extra_field(&extra_field,
+        &mut [&mut entry.uncompressed_size, &mut entry.compressed_size, &mut entry.local_header_offset])?;
+    entry.extra_field = extra_field;
+    Ok(entry)
+}
+
+struct LocalHeader {
+    flags: u16,
+    compression_method: u16,
+    last_mod_time: u16,
+    last_mod_date: u16,
+    crc: u32,
+    compressed_size: u64,
+    uncompressed_size: u64,
+    file_name: String,
+}
+
+// the local header of the entry and a reader of its data; the sizes in the local
+// header may be zero (data descriptor), so the data size comes from the central directory
+fn open_entry_data<'a>(zip_file: &'a File, entry: &CentralDirectoryEntry) -> io::Result<(LocalHeader, io::Take<&'a File>)> {
+    let mut file = zip_file;
+    file.seek(SeekFrom::Start(entry.local_header_offset))?;
+    let mut fixed_fields = [0u8; 30];
+    file.read_exact(&mut fixed_fields)?;
+    let mut cursor: Cursor<&[u8]> = Cursor::new(&fixed_fields);
+    let signature = read_u32_le(&mut cursor)?;
+    if signature != 0x04034b50 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Bad local header signature"));
+    }
+
+    // writers may put different versions needed in the two headers
+    let _version_needed = read_u16_le(&mut cursor)?;
+    let flags = read_u16_le(&mut cursor)?;
+    let compression_method = read_u16_le(&mut cursor)?;
+    let last_mod_time = read_u16_le(&mut cursor)?;
+    let last_mod_date = read_u16_le(&mut cursor)?;
+    let crc = read_u32_le(&mut cursor)?;
+    let mut compressed_size = read_u32_le(&mut cursor)? as u64;
+    let mut uncompressed_size = read_u32_le(&mut cursor)? as u64;
     let file_name_length = read_u16_le(&mut cursor)?;
     let extra_field_length = read_u16_le(&mut cursor)?;
 
     let mut file_name_bytes = vec![0; file_name_length as usize];
-    cursor.read_exact(&mut file_name_bytes)?;
-    let file_name = file_name_bytes.iter()
-        .map(|&b| if b.is_ascii() { b as char } else { '?' })
-        .collect::<String>();
-    
-    cursor.seek(SeekFrom::Current(extra_field_length as i64))?;
-    let start_of_data = cursor.position() as usize;
-
-    if let Some(eocd_pos) = find_eocd(&buffer) {
-
-        let mut cursor: Cursor<&[u8]> = Cursor::new(&buffer[eocd_pos..]);
-        cursor.seek(SeekFrom::Current(16))?;
-        let offset_of_start_of_central_directory = read_u32_le(&mut cursor).unwrap() as usize;
-        let start_of_central_directory = buffer[cursor.position() as usize..]
-        .windows(4)
-        .position(|window| window == &[0x50, 0x4B, 0x01, 0x02])
-        .unwrap_or_else(|| buffer.len()) + cursor.position() as usize;
-
-        assert!(offset_of_start_of_central_directory == start_of_central_directory);
-
-        let file_raw_data = &buffer[start_of_data..offset_of_start_of_central_directory];
-
-        let output_file_name = match compression_method {
-            0 => file_name,
-            8 => file_name,
-            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "Unsupported compression method")),
+    file.read_exact(&mut file_name_bytes)?;
+    let mut extra_field = vec![0; extra_field_length as usize];
+    file.read_exact(&mut extra_field)?;
+    read_zip64_extra_field(&extra_field, &mut [&mut uncompressed_size, &mut compressed_size])?;
+
+    let local_header = LocalHeader {
+        flags,
+        compression_method,
+        last_mod_time,
+        last_mod_date,
+        crc,
+        compressed_size,
+        uncompressed_size,
+        file_name: decode_entry_name(&file_name_bytes, flags, entry.version_made_by >> 8, &extra_field),
+    };
+    Ok((local_header, file.take(entry.compressed_size)))
+}
+
+// the local header repeats most fields of the central directory; with a data
+// descriptor (flag bit 3) its crc and sizes may be zero
+fn check_local_header(entry: &CentralDirectoryEntry, local_header: &LocalHeader) -> io::Result<()> {
+    let mut mismatches = Vec::new();
+    if local_header.flags != entry.flags {
+        mismatches.push("flags");
+    }
+    if local_header.compression_method != entry.compression_method {
+        mismatches.push("compression method");
+    }
+    if local_header.last_mod_time != entry.last_mod_time || local_header.last_mod_date != entry.last_mod_date {
+        mismatches.push("modification time");
+    }
+    if local_header.file_name != entry.file_name {
+        mismatches.push("file name");
+    }
+    if entry.flags & 0x8 == 0 {
+        if local_header.crc != entry.crc {
+            mismatches.push("CRC-32");
+        }
+        if local_header.compressed_size != entry.compressed_size {
+            mismatches.push("compressed size");
+        }
+        if local_header.uncompressed_size != entry.uncompressed_size {
+            mismatches.push("uncompressed size");
+        }
+    }
+    if !mismatches.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData,
+            format!("local header differs from central directory in {}", mismatches.join(", "))));
+    }
+    Ok(())
+}
+
+// deflate data is never decompressed beyond the size the central directory claims
+fn get_entry_reader<'a>(entry: &CentralDirectoryEntry, entry_data: io::Take<&'a File>) -> io::Result<Box<dyn Read + 'a>> {
+    match entry.compression_method {
+        0 => Ok(Box::new(entry_data)),
+        8 => {
+            let mut bit_reader = BitReader::new(entry_data);
+            bit_reader.set_output_limit(entry.uncompressed_size);
+            Ok(Box::new(bit_reader))
+        }
+        _ => Err(io::Error::new(io::ErrorKind::InvalidData, "Unsupported compression method")),
+    }
+}
+
+fn verify_size(file_name: &str, expected_size: u64, actual_size: u64) -> io::Result<()> {
+    if actual_size != expected_size {
+        return Err(io::Error::new(io::ErrorKind::InvalidData,
+            format!("Size mismatch for {}: expected {}, got {}", file_name, expected_size, actual_size)));
+    }
+    Ok(())
+}
+
+// copy everything from reader to writer, returns the number of bytes and their CRC-32
+fn copy_with_crc32(reader: &mut dyn Read, writer: &mut dyn Write) -> io::Result<(u64, u32)> {
+    let mut buffer = vec![0u8; 1 << 16];
+    let mut crc = Crc32::new();
+    let mut size: u64 = 0;
+    loop {
+        let count = match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(count) => count,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
         };
+        crc.update(&buffer[..count]);
+        writer.write_all(&buffer[..count])?;
+        size += count as u64;
+    }
+    Ok((size, crc.finalize()))
+}
+
+struct ExtractOptions {
+    allow_unsafe_paths: bool,
+    allow_setuid: bool,
+    limits: Limits,
+}
+
+// the extended timestamp if there is one, the MS-DOS date and time in local time otherwise
+fn get_modification_time(entry: &CentralDirectoryEntry) -> SystemTime {
+    let seconds = read_extended_timestamp_mtime(&entry.extra_field)
+        .unwrap_or_else(|| dos_date_time_to_unix(entry.last_mod_date, entry.last_mod_time));
+    unix_to_system_time(seconds)
+}
+
+// the permission bits of entries made on Unix, which keep st_mode in the upper
+// 16 bits of the external attributes; setuid and setgid only if allowed
+fn get_permissions(entry: &CentralDirectoryEntry, options: &ExtractOptions) -> Option<u32> {
+    let mode = entry.external_attributes >> 16;
+    if entry.version_made_by >> 8 != 3 || mode == 0 {
+        return None;
+    }
+    let mask = if options.allow_setuid { 0o7777 } else { 0o1777 };
+    Some(mode & mask)
+}
+
+#[cfg(unix)]
+fn set_permissions(path: &Path, permissions: Option<u32>) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    match permissions {
+        Some(mode) => fs::set_permissions(path, fs::Permissions::from_mode(mode)),
+        None => Ok(()),
+    }
+}
+
+#[cfg(not(unix))]
+fn set_permissions(_path: &Path, _permissions: Option<u32>) -> io::Result<()> {
+    Ok(())
+}
+
+// longest link target accepted, like PATH_MAX on Linux
+const MAX_LINK_TARGET_SIZE: u64 = 4096;
+
+// entries made on Unix with the file type of a symbolic link in their mode
+fn is_symlink(entry: &CentralDirectoryEntry) -> bool {
+    entry.version_made_by >> 8 == 3 && (entry.external_attributes >> 16) & 0o170000 == 0o120000
+}
+
+// the data of a link entry is its target
+fn read_link_target(zip_file: &File, entry: &CentralDirectoryEntry) -> io::Result<String> {
+    if entry.uncompressed_size > MAX_LINK_TARGET_SIZE {
+        return Err(io::Error::new(io::ErrorKind::InvalidData,
+            format!("Link target of {} is {} bytes long", entry.file_name, entry.uncompressed_size)));
+    }
+    let (_, entry_data) = open_entry_data(zip_file, entry)?;
+    let mut reader = get_entry_reader(entry, entry_data)?;
+    let mut target = Vec::new();
+    let (size, crc) = copy_with_crc32(&mut reader, &mut target)?;
+    verify_size(&entry.file_name, entry.uncompressed_size, size)?;
+    verify_crc32(&entry.file_name, entry.crc, crc)?;
+    String::from_utf8(target).map_err(|_| io::Error::new(io::ErrorKind::InvalidData,
+        format!("Link target of {} is not valid UTF-8", entry.file_name)))
+}
+
+#[cfg(unix)]
+fn create_symlink(target: &str, path: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, path)
+}
+
+// without symbolic links the target is written as a regular file, like unzip does
+#[cfg(not(unix))]
+fn create_symlink(target: &str, path: &Path) -> io::Result<()> {
+    fs::write(path, target)
+}
+
+// links are created after all other entries, so no entry can be written through one
+fn create_link(path: &Path, target: &str, options: &ExtractOptions) -> io::Result<()> {
+    if !options.allow_unsafe_paths {
+        check_no_symlinks(path.parent().unwrap_or(Path::new("")))?;
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_dir() => {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists,
+                format!("Cannot create link {}: a directory of that name exists", path.display())));
+        }
+        Ok(_) => fs::remove_file(path)?,
+        Err(_) => {}
+    }
+    create_symlink(target, path)
+}
+
+fn extract_entry(zip_file: &File, entry: &CentralDirectoryEntry, options: &ExtractOptions) -> io::Result<()> {
+    let path = &sanitize_entry_name(&entry.file_name, options.allow_unsafe_paths)?;
+    if entry.file_name.ends_with('/') {
+        if !options.allow_unsafe_paths {
+            check_no_symlinks(path)?;
+        }
+        fs::create_dir_all(path)?;
+        return Ok(());
+    }
+    if !options.allow_unsafe_paths {
+        check_no_symlinks(path.parent().unwrap_or(Path::new("")))?;
+    }
+    // an existing link is replaced instead of written through
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_symlink() => fs::remove_file(path)?,
+        _ => {}
+    }
 
-        let path = Path::new(&output_file_name);
-        if let Some(parent) = path.parent() {
-            fs::create_dir_all(parent)?;
+    let (_, entry_data) = open_entry_data(zip_file, entry)?;
+    let mut reader = get_entry_reader(entry, entry_data)?;
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    // the entry is decompressed straight into the output file
+    let mut output_file = File::create(path)?;
+    let (size, crc) = copy_with_crc32(&mut reader, &mut output_file)?;
+    verify_size(&entry.file_name, entry.uncompressed_size, size)?;
+    verify_crc32(&entry.file_name, entry.crc, crc)?;
+    output_file.set_modified(get_modification_time(entry))?;
+    set_permissions(path, get_permissions(entry, options))
+}
+
+// decompress the entry without writing it anywhere and check it against the central directory
+fn test_entry(zip_file: &File, entry: &CentralDirectoryEntry) -> io::Result<()> {
+    let (local_header, entry_data) = open_entry_data(zip_file, entry)?;
+    check_local_header(entry, &local_header)?;
+    let mut reader = get_entry_reader(entry, entry_data)?;
+    let (size, crc) = copy_with_crc32(&mut reader, &mut io::sink())?;
+    verify_size(&entry.file_name, entry.uncompressed_size, size)?;
+    verify_crc32(&entry.file_name, entry.crc, crc)
+}
+
+// like unzip -t, returns whether every entry is intact
+fn test_entries(zip_file_path: &str, zip_file: &File, archive: &Archive, limits: &Limits) -> bool {
+    println!("Archive:  {}", zip_file_path);
+    let mut all_ok = true;
+    let mut total_size = 0;
+    for entry in &archive.entries {
+        let result = limits.check_entry(&entry.file_name, entry.compressed_size, entry.uncompressed_size, total_size)
+            .map_err(io::Error::from)
+            .and_then(|_| test_entry(zip_file, entry));
+        total_size += entry.uncompressed_size;
+        match result {
+            Ok(()) => println!("    testing: {:<24} OK", entry.file_name),
+            Err(e) => {
+                println!("    testing: {:<24} FAILED ({})", entry.file_name, e);
+                all_ok = false;
+            }
         }
+    }
+    if all_ok {
+        println!("No errors detected in compressed data of {}.", zip_file_path);
+    } else {
+        println!("At least one error was detected in {}.", zip_file_path);
+    }
+    all_ok
+}
+
+// "1980-00-00 00:00" like unzip, from the MS-DOS date and time fields
+fn format_dos_date_time(date: u16, time: u16) -> String {
+    format!("{:04}-{:02}-{:02} {:02}:{:02}",
+        1980 + (date >> 9), (date >> 5) & 0xf, date & 0x1f, time >> 11, (time >> 5) & 0x3f)
+}
+
+fn get_method_name(entry: &CentralDirectoryEntry) -> String {
+    match entry.compression_method {
+        0 => "Stored".to_string(),
+        // flag bits 1 and 2 tell the deflate option: normal, maximum, fast or super fast
+        8 => format!("Defl:{}", ['N', 'X', 'F', 'S'][((entry.flags >> 1) & 3) as usize]),
+        method => format!("Unk:{:03}", method),
+    }
+}
+
+fn get_host_name(version_made_by: u16) -> String {
+    match version_made_by >> 8 {
+        0 => "MS-DOS".to_string(),
+        3 => "Unix".to_string(),
+        10 => "NTFS".to_string(),
+        19 => "OS X".to_string(),
+        host => format!("host {}", host),
+    }
+}
+
+// "2.0" from a version field
+fn format_version(version: u16) -> String {
+    let version = version & 0xff;
+    format!("{}.{}", version / 10, version % 10)
+}
+
+// space saved by compression in percent
+fn get_ratio(uncompressed_size: u64, compressed_size: u64) -> i64 {
+    if uncompressed_size == 0 {
+        return 0;
+    }
+    let saved = uncompressed_size as f64 - compressed_size as f64;
+    (saved * 100.0 / uncompressed_size as f64).round() as i64
+}
+
+// like unzip -l
+fn list_entries(zip_file_path: &str, archive: &Archive) {
+    println!("Archive:  {}", zip_file_path);
+    if !archive.comment.is_empty() {
+        println!("{}", archive.comment);
+    }
+    println!("  Length      Date    Time    Name");
+    println!("---------  ---------- -----   ----");
+    let mut total_uncompressed_size = 0;
+    for entry in &archive.entries {
+        println!("{:>9}  {}   {}", entry.uncompressed_size,
+            format_dos_date_time(entry.last_mod_date, entry.last_mod_time), entry.file_name);
+        total_uncompressed_size += entry.uncompressed_size;
+    }
+    println!("---------                     -------");
+    println!("{:>9}                     {} {}", total_uncompressed_size,
+        archive.entries.len(), if archive.entries.len() == 1 { "file" } else { "files" });
+}
+
+// like unzip -v, with the header fields of each entry
+fn list_entries_verbose(zip_file_path: &str, archive: &Archive) {
+    println!("Archive:  {}", zip_file_path);
+    if !archive.comment.is_empty() {
+        println!("{}", archive.comment);
+    }
+    println!(" Length   Method    Size  Cmpr    Date    Time   CRC-32   Name");
+    println!("--------  ------  ------- ---- ---------- ----- --------  ----");
+    let mut total_uncompressed_size = 0;
+    let mut total_compressed_size = 0;
+    for entry in &archive.entries {
+        println!("{:>8}  {:<6} {:>8} {:>3}% {} {:08x}  {}",
+            entry.uncompressed_size, get_method_name(entry), entry.compressed_size,
+            get_ratio(entry.uncompressed_size, entry.compressed_size),
+            format_dos_date_time(entry.last_mod_date, entry.last_mod_time), entry.crc, entry.file_name);
+        if !entry.comment.is_empty() {
+            println!("{}", entry.comment);
+        }
+        let extra_field_ids: Vec<String> = get_extra_field_ids(&entry.extra_field).iter()
+            .map(|id| format!("0x{:04x}", id))
+            .collect();
+        println!("          made by: {} {}, version needed: {}, flags: 0x{:04x}",
+            get_host_name(entry.version_made_by), format_version(entry.version_made_by),
+            format_version(entry.version_needed), entry.flags);
+        println!("          external attributes: 0x{:08x}, extra fields: {}",
+            entry.external_attributes,
+            if extra_field_ids.is_empty() { "none".to_string() } else { extra_field_ids.join(" ") });
+        total_uncompressed_size += entry.uncompressed_size;
+        total_compressed_size += entry.compressed_size;
+    }
+    println!("--------          -------  ---                            -------");
+    println!("{:>8}{:>17} {:>3}%                            {} {}",
+        total_uncompressed_size, total_compressed_size,
+        get_ratio(total_uncompressed_size, total_compressed_size),
+        archive.entries.len(), if archive.entries.len() == 1 { "file" } else { "files" });
+}
+
+struct Archive {
+    entries: Vec<CentralDirectoryEntry>,
+    comment: String,
+}
+
+// reads the central directory, None if there is no end of central directory record
+fn read_archive(zip_file: &mut File) -> io::Result<Option<Archive>> {
+    // the end of central directory record is 22 bytes followed by a comment of at most 65535 bytes
+    let file_size = zip_file.metadata()?.len();
+    let tail_offset = file_size - file_size.min(22 + 65535);
+    zip_file.seek(SeekFrom::Start(tail_offset))?;
+    let mut tail = Vec::new();
+    zip_file.read_to_end(&mut tail)?;
+
+    let eocd_pos = match find_eocd(&tail) {
+        Some(eocd_pos) => eocd_pos,
+        None => return Ok(None),
+    };
+
+    let mut cursor: Cursor<&[u8]> = Cursor::new(&tail[eocd_pos..]);
+    // signature, disk numbers, entries on this disk
+    cursor.seek(SeekFrom::Current(10))?;
+    let mut total_entries = read_u16_le(&mut cursor)? as u64;
+    let mut size_of_central_directory = read_u32_le(&mut cursor)? as u64;
+    let mut offset_of_start_of_central_directory = read_u32_le(&mut cursor)? as u64;
+    let comment_length = read_u16_le(&mut cursor)? as usize;
+    let comment_start = eocd_pos + 22;
+    // the archive comment has no flag of its own
+    let comment = decode_cp437(&tail[comment_start..tail.len().min(comment_start + comment_length)]);
+    let mut central_directory_end = tail_offset + eocd_pos as u64;
+    // a ZIP64 end of central directory locator right before the EOCD points to the ZIP64 record
+    if let Some(zip64_end_offset) = read_zip64_locator(&tail[..eocd_pos])? {
+        let mut zip64_end = [0u8; 56];
+        zip_file.seek(SeekFrom::Start(zip64_end_offset))?;
+        zip_file.read_exact(&mut zip64_end)?;
+        (total_entries, size_of_central_directory, offset_of_start_of_central_directory) =
+            read_zip64_end_of_central_directory(&zip64_end)?;
+        central_directory_end = zip64_end_offset;
+    }
+    if offset_of_start_of_central_directory.checked_add(size_of_central_directory)
+        .is_none_or(|end| end > central_directory_end) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Central directory offset out of range"));
+    }
+
+    // only the central directory is kept in memory, entries are streamed from the file
+    let mut central_directory = vec![0u8; size_of_central_directory as usize];
+    zip_file.seek(SeekFrom::Start(offset_of_start_of_central_directory))?;
+    zip_file.read_exact(&mut central_directory)?;
+
+    let mut cursor: Cursor<&[u8]> = Cursor::new(&central_directory);
+    let mut entries = Vec::new();
+    for _ in 0..total_entries {
+        entries.push(read_central_directory_entry(&mut cursor)?);
+    }
+    Ok(Some(Archive { entries, comment }))
+}
 
-        if compression_method == 0 {
-            let mut output_file = File::create(path)?;
-            output_file.write_all(file_raw_data)?;
+// "--max-size=BYTES" and the like, None if arg is not one of them
+fn parse_limit(arg: &str, limits: &mut Limits) -> Option<()> {
+    let (name, value) = arg.split_once('=')?;
+    let value: u64 = value.parse().ok()?;
+    match name {
+        "--max-size" => limits.max_total_size = value,
+        "--max-ratio" => limits.max_ratio = value,
+        "--max-entries" => limits.max_entries = value,
+        "--max-depth" => limits.max_depth = usize::try_from(value).ok()?,
+        _ => return None,
+    }
+    Some(())
+}
+
+fn main() -> io::Result<()> {
+    let args: Vec<String> = env::args().collect();
+    let mut option = None;
+    let mut options = ExtractOptions { allow_unsafe_paths: false, allow_setuid: false, limits: Limits::default() };
+    let mut zip_file_path = None;
+    let mut valid_arguments = true;
+    for arg in &args[1..] {
+        match arg.as_str() {
+            "-l" | "-v" | "-t" if option.is_none() => option = Some(arg.as_str()),
+            "--allow-unsafe-paths" => options.allow_unsafe_paths = true,
+            "--allow-setuid" => options.allow_setuid = true,
+            "--no-limits" => options.limits = Limits::unlimited(),
+            _ if parse_limit(arg, &mut options.limits).is_some() => {}
+            _ if !arg.starts_with('-') && zip_file_path.is_none() => zip_file_path = Some(arg),
+            _ => valid_arguments = false,
+        }
+    }
+    let zip_file_path = match zip_file_path {
+        Some(zip_file_path) if valid_arguments => zip_file_path,
+        _ => {
+            eprintln!("Usage: {} [-l|-v|-t] [--allow-unsafe-paths] [--allow-setuid] [--no-limits] [--max-size=BYTES] \
+                [--max-ratio=N] [--max-entries=N] [--max-depth=N] <input.zip>", args[0]);
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Incorrect arguments"));
+        }
+    };
+
+    let mut zip_file = File::open(zip_file_path)?;
+    // a truncated archive has lost its end of central directory record
+    let archive = match read_archive(&mut zip_file)? {
+        Some(archive) => archive,
+        None => return Err(io::Error::new(io::ErrorKind::InvalidData,
+            "End of central directory record not found - not a ZIP file or truncated")),
+    };
+
+    if option.is_none() || option == Some("-t") {
+        options.limits.check_entry_count(archive.entries.len() as u64)?;
+    }
+    match option {
+        Some("-t") => {
+            if !test_entries(zip_file_path, &zip_file, &archive, &options.limits) {
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        Some("-v") => {
+            list_entries_verbose(zip_file_path, &archive);
+            return Ok(());
+        }
+        Some(_) => {
+            list_entries(zip_file_path, &archive);
             return Ok(());
+        }
+        None => {}
+    }
+    let mut total_size = 0;
+    let mut links: Vec<(PathBuf, String)> = Vec::new();
+    for entry in &archive.entries {
+        options.limits.check_entry(&entry.file_name, entry.compressed_size, entry.uncompressed_size, total_size)?;
+        if is_symlink(entry) {
+            let path = sanitize_entry_name(&entry.file_name, options.allow_unsafe_paths)?;
+            links.push((path, read_link_target(&zip_file, entry)?));
         } else {
-            let mut bit_reader = BitReader::new(&file_raw_data);
-            let decoded_results = bit_reader.read_bitstream_blocks()?;
-            let mut output_file = File::create(output_file_name)?;
-            output_file.write_all(&decoded_results)?;
+            extract_entry(&zip_file, entry, &options)?;
+        }
+        total_size += entry.uncompressed_size;
+    }
+    // all targets are checked before the first link exists
+    if !options.allow_unsafe_paths {
+        let link_paths: Vec<PathBuf> = links.iter().map(|(path, _)| path.clone()).collect();
+        for (path, target) in &links {
+            check_link_target(path, target, &link_paths)?;
+        }
+    }
+    for (path, target) in &links {
+        create_link(path, target, &options)?;
+    }
+    // directory times and permissions are set last, extracting their contents
+    // changes the times and may need write permission
+    for entry in archive.entries.iter().rev().filter(|entry| entry.file_name.ends_with('/') && !is_symlink(entry)) {
+        let path = sanitize_entry_name(&entry.file_name, options.allow_unsafe_paths)?;
+        if !options.allow_unsafe_paths {
+            check_no_symlinks(&path)?;
+        }
+        File::open(&path)?.set_modified(get_modification_time(entry))?;
+        set_permissions(&path, get_permissions(entry, &options))?;
+    }
 
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_entry(version_made_by: u16, external_attributes: u32) -> CentralDirectoryEntry {
+        CentralDirectoryEntry {
+            version_made_by,
+            version_needed: 20,
+            flags: 0,
+            compression_method: 0,
+            last_mod_time: 0,
+            last_mod_date: 0x21,
+            crc: 0,
+            compressed_size: 0,
+            uncompressed_size: 0,
+            external_attributes,
+            local_header_offset: 0,
+            file_name: "file".to_string(),
+            extra_field: Vec::new(),
+            comment: String::new(),
         }
+    }
 
+    fn get_options(allow_setuid: bool) -> ExtractOptions {
+        ExtractOptions { allow_unsafe_paths: false, allow_setuid, limits: Limits::default() }
+    }
 
-    } else {
-        println!("EOCD not found - not a ZIP file or corrupted.");
+    #[test]
+    fn test_permissions() {
+        // a regular file as myzip and Info-ZIP zip write it on Unix, st_mode << 16
+        let setuid = get_entry(0x031e, 0o104755 << 16);
+        assert_eq!(get_permissions(&setuid, &get_options(false)), Some(0o755));
+        assert_eq!(get_permissions(&setuid, &get_options(true)), Some(0o4755));
+        let setgid = get_entry(0x031e, 0o102750 << 16);
+        assert_eq!(get_permissions(&setgid, &get_options(false)), Some(0o750));
+        assert_eq!(get_permissions(&setgid, &get_options(true)), Some(0o2750));
+
+        // the sticky bit is kept, the MS-DOS attributes in the low bits are ignored
+        let sticky = get_entry(0x031e, (0o041777 << 16) | 0x10);
+        assert_eq!(get_permissions(&sticky, &get_options(false)), Some(0o1777));
+
+        // hosts other than Unix, or no mode at all, leave the default permissions
+        assert_eq!(get_permissions(&get_entry(0x0014, 0o100644 << 16), &get_options(false)), None);
+        assert_eq!(get_permissions(&get_entry(0x0b14, 0o100644 << 16), &get_options(true)), None);
+        assert_eq!(get_permissions(&get_entry(0x031e, 0x20), &get_options(false)), None);
     }
 
-    Ok(())
-}
\ No newline at end of file
+    #[test]
+    fn test_format_dos_date_time() {
+        // 2024-03-09 14:07:58, the seconds are not shown
+        let date = ((2024 - 1980) << 9) | (3 << 5) | 9;
+        let time = (14 << 11) | (7 << 5) | 29;
+        assert_eq!(format_dos_date_time(date, time), "2024-03-09 14:07");
+        assert_eq!(format_dos_date_time(0x21, 0), "1980-01-01 00:00");
+        // an all zero date is printed as it is, like unzip does
+        assert_eq!(format_dos_date_time(0, 0), "1980-00-00 00:00");
+    }
+
+    #[test]
+    fn test_ratio() {
+        assert_eq!(get_ratio(1000, 250), 75);
+        assert_eq!(get_ratio(3, 1), 67);
+        assert_eq!(get_ratio(1000, 1000), 0);
+        // stored data with its overhead grows
+        assert_eq!(get_ratio(100, 120), -20);
+        assert_eq!(get_ratio(0, 0), 0);
+        assert_eq!(get_ratio(0, 2), 0);
+    }
+
+    #[test]
+    fn test_method_name() {
+        let mut entry = get_entry(0x031e, 0);
+        assert_eq!(get_method_name(&entry), "Stored");
+        entry.compression_method = 8;
+        assert_eq!(get_method_name(&entry), "Defl:N");
+        for (flags, name) in [(0x2, "Defl:X"), (0x4, "Defl:F"), (0x6, "Defl:S"), (0x806, "Defl:S")] {
+            entry.flags = flags;
+            assert_eq!(get_method_name(&entry), name);
+        }
+        entry.compression_method = 12;
+        assert_eq!(get_method_name(&entry), "Unk:012");
+    }
+}
@@ -0,0 +1,137 @@
+use crate::crc32::crc32;
+
+// general purpose flag bit 11, the file name and comment are UTF-8
+pub const FLAG_UTF8: u16 = 0x800;
+
+// code page 437, the original IBM PC character set, from 0x80 on; zip names
+// without the UTF-8 flag are in it, below 0x80 it is plain ASCII
+const CP437_HIGH: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å',
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ',
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»',
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐',
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧',
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀',
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩',
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{a0}',
+];
+
+pub fn decode_cp437(bytes: &[u8]) -> String {
+    bytes.iter()
+        .map(|&b| if b.is_ascii() { b as char } else { CP437_HIGH[(b - 0x80) as usize] })
+        .collect()
+}
+
+// the hosts of the upper byte of version made by whose tools write names in CP437:
+// MS-DOS and OS/2 FAT, Windows NTFS and VFAT
+fn is_cp437_host(host: u16) -> bool {
+    matches!(host, 0 | 11 | 14)
+}
+
+// names and comments are UTF-8 with flag bit 11; without it they are CP437 from
+// MS-DOS and Windows hosts, while Info-ZIP zip on Unix writes the raw UTF-8 names
+// without the flag, so those are UTF-8 if they are valid and CP437 otherwise
+pub fn decode_text(bytes: &[u8], flags: u16, host: u16) -> String {
+    if flags & FLAG_UTF8 != 0 {
+        String::from_utf8_lossy(bytes).into_owned()
+    } else if is_cp437_host(host) {
+        decode_cp437(bytes)
+    } else {
+        String::from_utf8(bytes.to_vec()).unwrap_or_else(|_| decode_cp437(bytes))
+    }
+}
+
+// the Info-ZIP Unicode Path extra field (0x7075): a version of 1, the CRC-32 of
+// the name in the header and the name in UTF-8; a field whose CRC-32 does not
+// match was left behind by a tool that changed the name and is ignored
+fn read_unicode_path_field(extra_field: &[u8], name: &[u8]) -> Option<String> {
+    let mut position = 0;
+    while position + 4 <= extra_field.len() {
+        let header_id = u16::from_le_bytes([extra_field[position], extra_field[position + 1]]);
+        let data_size = u16::from_le_bytes([extra_field[position + 2], extra_field[position + 3]]) as usize;
+        let data = extra_field.get(position + 4..position + 4 + data_size)?;
+        if header_id == 0x7075 && data.len() >= 5 && data[0] == 1 {
+            let name_crc = u32::from_le_bytes([data[1], data[2], data[3], data[4]]);
+            if name_crc == crc32(name) {
+                return String::from_utf8(data[5..].to_vec()).ok();
+            }
+        }
+        position += 4 + data_size;
+    }
+    None
+}
+
+// the name of an entry from its header fields, host is version made by >> 8
+pub fn decode_entry_name(name: &[u8], flags: u16, host: u16, extra_field: &[u8]) -> String {
+    if flags & FLAG_UTF8 == 0 {
+        if let Some(unicode_name) = read_unicode_path_field(extra_field, name) {
+            return unicode_name;
+        }
+    }
+    decode_text(name, flags, host)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_unicode_path_field(version: u8, name_crc: u32, unicode_name: &str) -> Vec<u8> {
+        let mut extra_field = Vec::new();
+        extra_field.extend(&0x7075u16.to_le_bytes());
+        extra_field.extend(&(5 + unicode_name.len() as u16).to_le_bytes());
+        extra_field.push(version);
+        extra_field.extend(&name_crc.to_le_bytes());
+        extra_field.extend(unicode_name.as_bytes());
+        extra_field
+    }
+
+    #[test]
+    fn test_cp437() {
+        assert_eq!(decode_cp437(b"plain.txt"), "plain.txt");
+        assert_eq!(decode_cp437(&[0x80, 0x81, 0x9b, 0xb0, 0xe1, 0xff]), "Çü¢░ß\u{a0}");
+        // "résumé" as written by MS-DOS and old Windows tools
+        assert_eq!(decode_cp437(&[b'r', 0x82, b's', b'u', b'm', 0x82]), "résumé");
+        assert_eq!(decode_cp437(&(0x80..=0xff).collect::<Vec<u8>>()).chars().count(), 128);
+    }
+
+    #[test]
+    fn test_utf8_flag() {
+        let name = "数据/café.txt";
+        assert_eq!(decode_entry_name(name.as_bytes(), FLAG_UTF8, 0, &[]), name);
+        assert_eq!(decode_entry_name(name.as_bytes(), FLAG_UTF8, 3, &[]), name);
+        // the same bytes without the flag from an MS-DOS or Windows host are CP437
+        assert_eq!(decode_entry_name("é".as_bytes(), 0, 0, &[]), "├⌐");
+        assert_eq!(decode_entry_name("é".as_bytes(), 0, 11, &[]), "├⌐");
+        assert_eq!(decode_entry_name("é".as_bytes(), 0, 14, &[]), "├⌐");
+        assert_eq!(decode_text(&[0xff, b'a'], FLAG_UTF8, 0), "\u{fffd}a");
+    }
+
+    #[test]
+    fn test_unix_names() {
+        // Info-ZIP zip on Linux writes UTF-8 names without the flag
+        let name = "café 数据.txt";
+        assert_eq!(decode_entry_name(name.as_bytes(), 0, 3, &[]), name);
+        assert_eq!(decode_entry_name(name.as_bytes(), 0, 19, &[]), name);
+        // names that are not UTF-8 are still CP437
+        assert_eq!(decode_entry_name(&[b'r', 0x82, b's', b'u', b'm', 0x82], 0, 3, &[]), "résumé");
+        assert_eq!(decode_text(b"plain.txt", 0, 3), "plain.txt");
+    }
+
+    #[test]
+    fn test_unicode_path_field() {
+        let name = [b'r', 0x82, b's', b'u', b'm', 0x82];
+        let mut extra_field = vec![0x55, 0x54, 5, 0, 1, 0, 0, 0, 0];
+        extra_field.extend(get_unicode_path_field(1, crc32(&name), "résumé_履歴書"));
+        assert_eq!(decode_entry_name(&name, 0, 0, &extra_field), "résumé_履歴書");
+        // with the UTF-8 flag the name itself is used
+        assert_eq!(decode_entry_name(b"cv", FLAG_UTF8, 0, &get_unicode_path_field(1, crc32(b"cv"), "other")), "cv");
+
+        // a stale field, an unknown version or a truncated field fall back to CP437
+        let stale = get_unicode_path_field(1, crc32(b"old name"), "résumé_履歴書");
+        assert_eq!(decode_entry_name(&name, 0, 0, &stale), "résumé");
+        let unknown_version = get_unicode_path_field(2, crc32(&name), "résumé_履歴書");
+        assert_eq!(decode_entry_name(&name, 0, 0, &unknown_version), "résumé");
+        let truncated = &get_unicode_path_field(1, crc32(&name), "résumé_履歴書")[..12];
+        assert_eq!(decode_entry_name(&name, 0, 0, truncated), "résumé");
+    }
+}
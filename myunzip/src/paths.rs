@@ -0,0 +1,203 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+fn unsafe_path_error(name: &str, reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData,
+        format!("Unsafe path {:?}: {} (use --allow-unsafe-paths to extract it anyway)", name, reason))
+}
+
+// the path an entry is extracted to, relative to the current directory; names are
+// untrusted, so absolute paths, drive prefixes, backslashes and ".." are rejected
+// unless allow_unsafe is set
+pub fn sanitize_entry_name(name: &str, allow_unsafe: bool) -> io::Result<PathBuf> {
+    if allow_unsafe {
+        return Ok(PathBuf::from(name));
+    }
+    if name.contains('\0') {
+        return Err(unsafe_path_error(name, "contains a NUL byte"));
+    }
+    // zip names always use '/', a backslash may be a windows separator
+    if name.contains('\\') {
+        return Err(unsafe_path_error(name, "contains a backslash"));
+    }
+    if name.starts_with('/') {
+        return Err(unsafe_path_error(name, "absolute path"));
+    }
+    let bytes = name.as_bytes();
+    if bytes.len() >= 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':' {
+        return Err(unsafe_path_error(name, "drive prefix"));
+    }
+
+    let mut path = PathBuf::new();
+    for component in name.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => return Err(unsafe_path_error(name, "contains \"..\"")),
+            component => path.push(component),
+        }
+    }
+    if path.as_os_str().is_empty() {
+        return Err(unsafe_path_error(name, "empty path"));
+    }
+    Ok(path)
+}
+
+// a symbolic link is only created if its target stays inside the extraction
+// directory; link_path is the sanitized path of the link itself and links the
+// paths of all links in the archive, which may not exist on disk yet
+pub fn check_link_target(link_path: &Path, target: &str, links: &[PathBuf]) -> io::Result<()> {
+    let name = link_path.to_string_lossy();
+    let error = |reason: &str| io::Error::new(io::ErrorKind::InvalidData,
+        format!("Unsafe link {} -> {:?}: {} (use --allow-unsafe-paths to extract it anyway)", name, target, reason));
+    if target.is_empty() {
+        return Err(error("empty target"));
+    }
+    if target.contains('\0') {
+        return Err(error("contains a NUL byte"));
+    }
+    if target.contains('\\') {
+        return Err(error("contains a backslash"));
+    }
+    if target.starts_with('/') {
+        return Err(error("absolute target"));
+    }
+    let bytes = target.as_bytes();
+    if bytes.len() >= 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':' {
+        return Err(error("drive prefix"));
+    }
+
+    // the target is resolved from the directory holding the link; ".." can only be
+    // resolved by name if nothing on the way is another link, like "y/.." with y -> "."
+    let components: Vec<&str> = target.split('/').filter(|name| !name.is_empty() && *name != ".").collect();
+    let mut path = link_path.parent().map_or_else(PathBuf::new, Path::to_path_buf);
+    for (index, component) in components.iter().enumerate() {
+        if *component == ".." {
+            if !path.pop() {
+                return Err(error("points outside the extraction directory"));
+            }
+            continue;
+        }
+        path.push(component);
+        // the link the target ends at is checked on its own
+        if index + 1 < components.len() && (links.contains(&path) || is_symlink(&path)) {
+            return Err(error(&format!("goes through the link {}", path.display())));
+        }
+    }
+    Ok(())
+}
+
+fn is_symlink(path: &Path) -> bool {
+    fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_symlink())
+}
+
+// fails if the path or any of its parents is a symbolic link, which would
+// redirect what is written there to somewhere else; missing parts are fine
+pub fn check_no_symlinks(path: &Path) -> io::Result<()> {
+    for ancestor in path.ancestors().filter(|ancestor| !ancestor.as_os_str().is_empty()) {
+        match fs::symlink_metadata(ancestor) {
+            Ok(metadata) if metadata.file_type().is_symlink() => {
+                return Err(io::Error::new(io::ErrorKind::InvalidData,
+                    format!("Unsafe path {}: {} is a symbolic link (use --allow-unsafe-paths to extract it anyway)",
+                        path.display(), ancestor.display())));
+            }
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the names of all central directory records, found by their signature
+    fn read_entry_names(zip: &[u8]) -> Vec<String> {
+        let mut names = Vec::new();
+        for position in 0..zip.len().saturating_sub(46) {
+            if zip[position..position + 4] == [0x50, 0x4b, 0x01, 0x02] {
+                let name_length = u16::from_le_bytes([zip[position + 28], zip[position + 29]]) as usize;
+                let name = &zip[position + 46..position + 46 + name_length];
+                names.push(String::from_utf8_lossy(name).into_owned());
+            }
+        }
+        names
+    }
+
+    #[test]
+    fn test_safe_names() {
+        assert_eq!(sanitize_entry_name("a.txt", false).unwrap(), PathBuf::from("a.txt"));
+        assert_eq!(sanitize_entry_name("dir/", false).unwrap(), PathBuf::from("dir"));
+        assert_eq!(sanitize_entry_name("dir/./sub//b.txt", false).unwrap(), PathBuf::from("dir/sub/b.txt"));
+        assert_eq!(sanitize_entry_name("..a/b..", false).unwrap(), PathBuf::from("..a/b.."));
+    }
+
+    #[test]
+    fn test_unsafe_names() {
+        for name in ["../evil.txt", "a/../../evil.txt", "a/..", "/tmp/evil.txt", "C:/evil.txt",
+                     "c:evil.txt", "..\\evil.txt", "a\\b.txt", "", "./", "a\0b"] {
+            let error = sanitize_entry_name(name, false).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData, "{:?}", name);
+        }
+        assert_eq!(sanitize_entry_name("../evil.txt", true).unwrap(), PathBuf::from("../evil.txt"));
+    }
+
+    #[test]
+    fn test_crafted_archives() {
+        let zip = fs::read("../testdata/unsafe_paths/traversal.zip").unwrap();
+        let names = read_entry_names(&zip);
+        assert_eq!(names, ["safe/ok.txt", "../evil.txt", "safe/../../evil.txt", "/tmp/evil.txt",
+                           "C:\\evil.txt", "..\\evil.txt"]);
+        assert!(sanitize_entry_name(&names[0], false).is_ok());
+        for name in &names[1..] {
+            assert!(sanitize_entry_name(name, false).is_err(), "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn test_link_targets() {
+        for (link, target) in [("link", "file.txt"), ("link", "./dir/../file.txt"), ("dir/link", "../file.txt"),
+                               ("dir/sub/link", "../../dir//file.txt"), ("link", "dir/")] {
+            assert!(check_link_target(Path::new(link), target, &[]).is_ok(), "{} -> {}", link, target);
+        }
+        for (link, target) in [("link", ".."), ("link", "../file.txt"), ("dir/link", "../../file.txt"),
+                               ("link", "dir/../../file.txt"), ("link", "/etc/passwd"), ("link", "C:/file.txt"),
+                               ("link", "..\\file.txt"), ("link", ""), ("link", "a\0b")] {
+            let error = check_link_target(Path::new(link), target, &[]).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData, "{} -> {}", link, target);
+        }
+    }
+
+    #[test]
+    fn test_chained_link_targets() {
+        // y -> "." makes "y/.." the parent of the extraction directory
+        let links = [PathBuf::from("y"), PathBuf::from("x"), PathBuf::from("dir/link")];
+        assert!(check_link_target(Path::new("y"), ".", &links).is_ok());
+        assert!(check_link_target(Path::new("x"), "y/..", &links).is_err());
+        assert!(check_link_target(Path::new("x"), "dir/link/../file.txt", &links).is_err());
+        assert!(check_link_target(Path::new("dir/other"), "link/a", &links).is_err());
+        // ending at another link is fine, that link is checked itself
+        assert!(check_link_target(Path::new("x"), "y", &links).is_ok());
+        assert!(check_link_target(Path::new("x"), "dir/link", &links).is_ok());
+        assert!(check_link_target(Path::new("x"), "dir/../y", &links).is_ok());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_no_symlinks() {
+        let dir = std::env::temp_dir().join(format!("paths_test_{}", std::process::id()));
+        fs::create_dir_all(dir.join("real")).unwrap();
+        // the temporary directory itself may be behind a link
+        let dir = fs::canonicalize(dir).unwrap();
+        std::os::unix::fs::symlink("real", dir.join("link")).unwrap();
+        assert!(check_no_symlinks(&dir.join("real/missing/file.txt")).is_ok());
+        assert!(check_no_symlinks(&dir.join("link")).is_err());
+        assert!(check_no_symlinks(&dir.join("link/file.txt")).is_err());
+        // links already on disk count as well
+        assert!(check_link_target(&dir.join("x"), "link/..", &[]).is_err());
+        assert!(check_link_target(&dir.join("x"), "real/..", &[]).is_ok());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
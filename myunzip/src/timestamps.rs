@@ -0,0 +1,279 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// the MS-DOS date and time are local time, like Info-ZIP zip and unzip and the
+// Windows tools write and read them; std knows no time zones, so the offset from
+// UTC comes from localtime_r of the C library, which honours TZ
+#[cfg(unix)]
+mod local_time {
+    use std::os::raw::{c_char, c_int, c_long};
+
+    // struct tm as glibc, musl, macOS and the BSDs define it
+    #[repr(C)]
+    struct Tm {
+        tm_sec: c_int,
+        tm_min: c_int,
+        tm_hour: c_int,
+        tm_mday: c_int,
+        tm_mon: c_int,
+        tm_year: c_int,
+        tm_wday: c_int,
+        tm_yday: c_int,
+        tm_isdst: c_int,
+        tm_gmtoff: c_long,
+        tm_zone: *const c_char,
+    }
+
+    extern "C" {
+        fn localtime_r(time: *const c_long, result: *mut Tm) -> *mut Tm;
+    }
+
+    // seconds east of UTC at a UTC time, 0 if the C library cannot tell
+    pub fn get_utc_offset(seconds: i64) -> i64 {
+        // a 32 bit time_t ends in 2038
+        let time = c_long::try_from(seconds).unwrap_or(if seconds < 0 { c_long::MIN } else { c_long::MAX });
+        let mut tm = Tm {
+            tm_sec: 0,
+            tm_min: 0,
+            tm_hour: 0,
+            tm_mday: 0,
+            tm_mon: 0,
+            tm_year: 0,
+            tm_wday: 0,
+            tm_yday: 0,
+            tm_isdst: 0,
+            tm_gmtoff: 0,
+            tm_zone: std::ptr::null(),
+        };
+        // localtime_r only writes to tm, which lives until the end of the function
+        let result = unsafe { localtime_r(&time, &mut tm) };
+        if result.is_null() {
+            0
+        } else {
+            tm.tm_gmtoff as i64
+        }
+    }
+}
+
+#[cfg(unix)]
+use local_time::get_utc_offset;
+
+// elsewhere the MS-DOS fields are taken as UTC
+#[cfg(not(unix))]
+fn get_utc_offset(_seconds: i64) -> i64 {
+    0
+}
+
+// days since 1970-01-01 of a proleptic gregorian date
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+// (year, month, day) of a number of days since 1970-01-01
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+pub fn system_time_to_unix(time: SystemTime) -> i64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(duration) => duration.as_secs() as i64,
+        Err(error) => -(error.duration().as_secs_f64().ceil() as i64),
+    }
+}
+
+pub fn unix_to_system_time(seconds: i64) -> SystemTime {
+    if seconds >= 0 {
+        UNIX_EPOCH + Duration::from_secs(seconds as u64)
+    } else {
+        UNIX_EPOCH - Duration::from_secs(seconds.unsigned_abs())
+    }
+}
+
+// (date, time) fields of a wall clock time in seconds since 1970-01-01 00:00;
+// MS-DOS times run from 1980 to 2107 in steps of 2 seconds
+fn seconds_to_dos_date_time(seconds: i64) -> (u16, u16) {
+    let min_seconds = days_from_civil(1980, 1, 1) * 86400;
+    let max_seconds = days_from_civil(2107, 12, 31) * 86400 + 86399;
+    let seconds = seconds.clamp(min_seconds, max_seconds);
+    let (year, month, day) = civil_from_days(seconds.div_euclid(86400));
+    let second_of_day = seconds.rem_euclid(86400);
+    let date = (((year - 1980) << 9) | (month << 5) | day) as u16;
+    let time = (((second_of_day / 3600) << 11) | ((second_of_day / 60 % 60) << 5) | (second_of_day % 60 / 2)) as u16;
+    (date, time)
+}
+
+fn dos_date_time_to_seconds(date: u16, time: u16) -> i64 {
+    let year = 1980 + (date >> 9) as i64;
+    let month = ((date >> 5) & 0xf).max(1) as i64;
+    let day = (date & 0x1f).max(1) as i64;
+    let hours = (time >> 11) as i64;
+    let minutes = ((time >> 5) & 0x3f) as i64;
+    let seconds = 2 * (time & 0x1f) as i64;
+    days_from_civil(year, month, day) * 86400 + hours * 3600 + minutes * 60 + seconds
+}
+
+// the UTC time of a local time; the offset is looked up twice so that times
+// next to a daylight saving change get the offset in effect at that time, like
+// mktime does it
+fn local_to_utc(local_seconds: i64, get_utc_offset: impl Fn(i64) -> i64) -> i64 {
+    let guess = local_seconds - get_utc_offset(local_seconds);
+    local_seconds - get_utc_offset(guess)
+}
+
+pub fn unix_to_dos_date_time(seconds: i64) -> (u16, u16) {
+    seconds_to_dos_date_time(seconds + get_utc_offset(seconds))
+}
+
+pub fn dos_date_time_to_unix(date: u16, time: u16) -> i64 {
+    local_to_utc(dos_date_time_to_seconds(date, time), get_utc_offset)
+}
+
+// the extended timestamp extra field for the local header and for the central
+// directory, which holds only the modification time; times that do not fit
+// into 32 bits are left out
+pub fn get_extended_timestamp_fields(modified: i64, accessed: Option<i64>, created: Option<i64>) -> (Vec<u8>, Vec<u8>) {
+    let mut flags = 0u8;
+    let mut times = Vec::new();
+    for (bit, time) in [(1u8, Some(modified)), (2, accessed), (4, created)] {
+        if let Some(time) = time.and_then(|time| i32::try_from(time).ok()) {
+            flags |= bit;
+            times.push(time);
+        }
+    }
+    if flags == 0 {
+        return (Vec::new(), Vec::new());
+    }
+
+    let mut local = Vec::new();
+    local.extend(&0x5455u16.to_le_bytes());
+    local.extend(&(1 + 4 * times.len() as u16).to_le_bytes());
+    local.push(flags);
+    for time in &times {
+        local.extend(&time.to_le_bytes());
+    }
+
+    let mut central = Vec::new();
+    central.extend(&0x5455u16.to_le_bytes());
+    if flags & 1 != 0 {
+        central.extend(&5u16.to_le_bytes());
+        central.push(flags);
+        central.extend(&times[0].to_le_bytes());
+    } else {
+        central.extend(&1u16.to_le_bytes());
+        central.push(flags);
+    }
+    (local, central)
+}
+
+// the modification time of an extended timestamp field in an extra field
+pub fn read_extended_timestamp_mtime(extra_field: &[u8]) -> Option<i64> {
+    let mut position = 0;
+    while position + 4 <= extra_field.len() {
+        let header_id = u16::from_le_bytes([extra_field[position], extra_field[position + 1]]);
+        let data_size = u16::from_le_bytes([extra_field[position + 2], extra_field[position + 3]]) as usize;
+        let data = extra_field.get(position + 4..position + 4 + data_size)?;
+        if header_id == 0x5455 && data.len() >= 5 && data[0] & 1 != 0 {
+            return Some(i32::from_le_bytes([data[1], data[2], data[3], data[4]]) as i64);
+        }
+        position += 4 + data_size;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_civil_days() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(2000, 3, 1), 11017);
+        assert_eq!(days_from_civil(1969, 12, 31), -1);
+        for days in [-800000, -1, 0, 59, 60, 11016, 11017, 19000, 50000] {
+            let (year, month, day) = civil_from_days(days);
+            assert_eq!(days_from_civil(year, month, day), days);
+        }
+    }
+
+    #[test]
+    fn test_dos_date_time() {
+        // 2024-02-29 13:45:30
+        let seconds = 1709214330;
+        let (date, time) = seconds_to_dos_date_time(seconds);
+        assert_eq!(date, (44 << 9) | (2 << 5) | 29);
+        assert_eq!(time, (13 << 11) | (45 << 5) | 15);
+        assert_eq!(dos_date_time_to_seconds(date, time), seconds);
+        // odd seconds are rounded down
+        assert_eq!(dos_date_time_to_seconds(date, seconds_to_dos_date_time(seconds + 1).1), seconds);
+        // times before 1980 are clamped
+        assert_eq!(seconds_to_dos_date_time(0), ((1 << 5) | 1, 0));
+    }
+
+    // Central European Time, +1 hour, with summer time, +2 hours, in 2024 from
+    // 2024-03-31 01:00 UTC to 2024-10-27 01:00 UTC
+    fn get_cet_offset(seconds: i64) -> i64 {
+        if (1711846800..1729990800).contains(&seconds) { 7200 } else { 3600 }
+    }
+
+    #[test]
+    fn test_local_time() {
+        // 2024-02-29 13:45:30 UTC is 14:45:30 in winter
+        let (date, time) = seconds_to_dos_date_time(1709214330 + get_cet_offset(1709214330));
+        assert_eq!(time, (14 << 11) | (45 << 5) | 15);
+        assert_eq!(local_to_utc(dos_date_time_to_seconds(date, time), get_cet_offset), 1709214330);
+        // 2024-07-01 12:00 local is 10:00 UTC in summer
+        let local_seconds = days_from_civil(2024, 7, 1) * 86400 + 12 * 3600;
+        assert_eq!(local_to_utc(local_seconds, get_cet_offset), local_seconds - 7200);
+        // right after the change to summer time, 03:30 local is 01:30 UTC
+        let local_seconds = days_from_civil(2024, 3, 31) * 86400 + 3 * 3600 + 1800;
+        assert_eq!(local_to_utc(local_seconds, get_cet_offset), local_seconds - 7200);
+        assert_eq!(local_to_utc(local_seconds, |_| 0), local_seconds);
+    }
+
+    #[test]
+    fn test_dos_date_time_round_trip() {
+        // whatever the time zone of the test, even seconds come back unchanged
+        for seconds in [1709214330, 1719835200, 1711846800, 1729990800 + 3600] {
+            let (date, time) = unix_to_dos_date_time(seconds);
+            assert_eq!(dos_date_time_to_unix(date, time), seconds);
+            assert_eq!((date, time), seconds_to_dos_date_time(seconds + get_utc_offset(seconds)));
+        }
+    }
+
+    #[test]
+    fn test_system_time() {
+        for seconds in [-86400, 0, 1709214330] {
+            assert_eq!(system_time_to_unix(unix_to_system_time(seconds)), seconds);
+        }
+    }
+
+    #[test]
+    fn test_extended_timestamp_fields() {
+        let (local, central) = get_extended_timestamp_fields(1709214330, Some(1709214331), None);
+        assert_eq!(local.len(), 4 + 9);
+        assert_eq!(&local[..5], &[0x55, 0x54, 9, 0, 3]);
+        assert_eq!(&central[..5], &[0x55, 0x54, 5, 0, 3]);
+        assert_eq!(read_extended_timestamp_mtime(&local), Some(1709214330));
+        assert_eq!(read_extended_timestamp_mtime(&central), Some(1709214330));
+
+        // other fields before it are skipped, times past 2038 do not fit
+        let mut extra_field = vec![0x01, 0x00, 0x08, 0x00, 1, 2, 3, 4, 5, 6, 7, 8];
+        extra_field.extend(&central);
+        assert_eq!(read_extended_timestamp_mtime(&extra_field), Some(1709214330));
+        assert_eq!(get_extended_timestamp_fields(1 << 32, None, None), (Vec::new(), Vec::new()));
+        assert_eq!(read_extended_timestamp_mtime(&[0x55, 0x54, 5, 0, 1]), None);
+    }
+}
@@ -0,0 +1,142 @@
+use std::io::{self, Cursor, Seek, SeekFrom};
+use crate::{read_u16_le, read_u32_le, read_u64_le};
+
+// fields set to 0xffffffff are replaced by the values of the ZIP64 extended
+// information extra field, which holds only those and in the order given
+pub fn read_zip64_extra_field(extra_field: &[u8], fields: &mut [&mut u64]) -> io::Result<()> {
+    let mut cursor: Cursor<&[u8]> = Cursor::new(extra_field);
+    while (cursor.position() as usize) + 4 <= extra_field.len() {
+        let header_id = read_u16_le(&mut cursor)?;
+        let data_size = read_u16_le(&mut cursor)?;
+        let data_end = cursor.position() + data_size as u64;
+        if header_id == 0x0001 {
+            let bad_field = |_| io::Error::new(io::ErrorKind::InvalidData, "ZIP64 extra field too short");
+            for field in fields.iter_mut().filter(|field| ***field == 0xffffffff) {
+                **field = read_u64_le(&mut cursor).map_err(bad_field)?;
+            }
+            if cursor.position() > data_end {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "ZIP64 extra field too short"));
+            }
+        }
+        cursor.set_position(data_end);
+    }
+    Ok(())
+}
+
+// the offset of the ZIP64 end of central directory record if the 20 bytes before the EOCD are its locator
+pub fn read_zip64_locator(before_eocd: &[u8]) -> io::Result<Option<u64>> {
+    if before_eocd.len() < 20 {
+        return Ok(None);
+    }
+    let mut cursor: Cursor<&[u8]> = Cursor::new(&before_eocd[before_eocd.len() - 20..]);
+    if read_u32_le(&mut cursor)? != 0x07064b50 {
+        return Ok(None);
+    }
+    // disk with the ZIP64 record
+    cursor.seek(SeekFrom::Current(4))?;
+    Ok(Some(read_u64_le(&mut cursor)?))
+}
+
+// (total entries, central directory size, central directory offset) of the
+// fixed part of a ZIP64 end of central directory record
+pub fn read_zip64_end_of_central_directory(record: &[u8]) -> io::Result<(u64, u64, u64)> {
+    let mut cursor: Cursor<&[u8]> = Cursor::new(record);
+    if read_u32_le(&mut cursor)? != 0x06064b50 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Bad ZIP64 end of central directory signature"));
+    }
+    // record size, versions, disk numbers, entries on this disk
+    cursor.seek(SeekFrom::Current(28))?;
+    let total_entries = read_u64_le(&mut cursor)?;
+    let size_of_central_directory = read_u64_le(&mut cursor)?;
+    let offset_of_start_of_central_directory = read_u64_le(&mut cursor)?;
+    Ok((total_entries, size_of_central_directory, offset_of_start_of_central_directory))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::read_central_directory_entry;
+
+    fn get_zip64_extra_field(values: &[u64]) -> Vec<u8> {
+        let mut extra_field = Vec::new();
+        extra_field.extend(&0x0001u16.to_le_bytes());
+        extra_field.extend(&(values.len() as u16 * 8).to_le_bytes());
+        for value in values {
+            extra_field.extend(&value.to_le_bytes());
+        }
+        extra_field
+    }
+
+    // a central directory header with the given 32 bit fields and extra field
+    fn get_central_directory_header(compressed_size: u32, uncompressed_size: u32, local_header_offset: u32,
+        extra_field: &[u8]) -> Vec<u8> {
+        let mut header = Vec::new();
+        header.extend(&0x02014b50u32.to_le_bytes());
+        header.extend(&[30, 3]);
+        header.extend(&45u16.to_le_bytes());
+        // flags, method, time, date, CRC-32
+        header.extend(&[0; 12]);
+        header.extend(&compressed_size.to_le_bytes());
+        header.extend(&uncompressed_size.to_le_bytes());
+        header.extend(&5u16.to_le_bytes());
+        header.extend(&(extra_field.len() as u16).to_le_bytes());
+        // comment length, disk number start, internal and external attributes
+        header.extend(&[0; 10]);
+        header.extend(&local_header_offset.to_le_bytes());
+        header.extend(b"large");
+        header.extend(extra_field);
+        header
+    }
+
+    #[test]
+    fn test_zip64_central_directory_entry() {
+        // a timestamp field first, then the three values in their fixed order
+        let mut extra_field = vec![0x55, 0x54, 5, 0, 1, 0, 0, 0, 0];
+        extra_field.extend(get_zip64_extra_field(&[6 << 30, 5 << 30, 0x1_0000_0000]));
+        let header = get_central_directory_header(0xffffffff, 0xffffffff, 0xffffffff, &extra_field);
+        let entry = read_central_directory_entry(&mut Cursor::new(&header)).unwrap();
+        assert_eq!(entry.file_name, "large");
+        assert_eq!(entry.uncompressed_size, 6 << 30);
+        assert_eq!(entry.compressed_size, 5 << 30);
+        assert_eq!(entry.local_header_offset, 0x1_0000_0000);
+
+        // only the fields set to 0xffffffff are in the extra field
+        let header = get_central_directory_header(1000, 2000, 0xffffffff, &get_zip64_extra_field(&[5 << 30]));
+        let entry = read_central_directory_entry(&mut Cursor::new(&header)).unwrap();
+        assert_eq!((entry.compressed_size, entry.uncompressed_size, entry.local_header_offset), (1000, 2000, 5 << 30));
+
+        // a field without its value is an error
+        let header = get_central_directory_header(0xffffffff, 0xffffffff, 0, &get_zip64_extra_field(&[6 << 30]));
+        let error = read_central_directory_entry(&mut Cursor::new(&header)).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_zip64_end_of_central_directory() {
+        let (total_entries, central_directory_size, central_directory_offset) = (70000u64, 3_500_000u64, 5u64 << 30);
+        let record_offset = central_directory_offset + central_directory_size;
+        let mut tail = Vec::new();
+        tail.extend(&0x06064b50u32.to_le_bytes());
+        tail.extend(&44u64.to_le_bytes());
+        tail.extend(&[30, 3]);
+        tail.extend(&45u16.to_le_bytes());
+        tail.extend(&[0; 8]);
+        tail.extend(&total_entries.to_le_bytes());
+        tail.extend(&total_entries.to_le_bytes());
+        tail.extend(&central_directory_size.to_le_bytes());
+        tail.extend(&central_directory_offset.to_le_bytes());
+        tail.extend(&0x07064b50u32.to_le_bytes());
+        tail.extend(&0u32.to_le_bytes());
+        tail.extend(&record_offset.to_le_bytes());
+        tail.extend(&1u32.to_le_bytes());
+
+        assert_eq!(read_zip64_locator(&tail).unwrap(), Some(record_offset));
+        assert_eq!(read_zip64_end_of_central_directory(&tail[..56]).unwrap(),
+            (total_entries, central_directory_size, central_directory_offset));
+
+        // no locator before an ordinary EOCD, a record with the wrong signature
+        assert_eq!(read_zip64_locator(&tail[..56]).unwrap(), None);
+        assert_eq!(read_zip64_locator(&tail[56..70]).unwrap(), None);
+        assert!(read_zip64_end_of_central_directory(&tail[56..]).is_err());
+    }
+}
@@ -0,0 +1,138 @@
+use std::fs;
+use std::process::{Command, Output};
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffffffffu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xedb88320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+// offsets of the fields changed by the tests
+const LOCAL_HEADER_TIME: usize = 10;
+const CENTRAL_VERSION_NEEDED: usize = 6;
+const CENTRAL_CRC: usize = 16;
+const CENTRAL_COMPRESSED_SIZE: usize = 20;
+
+// an archive of stored entries, with the offset of each central directory header
+fn build_zip(entries: &[(&str, &[u8])]) -> (Vec<u8>, Vec<usize>) {
+    let mut zip = Vec::new();
+    let mut central_directory = Vec::new();
+    let mut central_offsets = Vec::new();
+    for (name, data) in entries {
+        let mut fields = Vec::new();
+        fields.extend(&20u16.to_le_bytes());
+        fields.extend(&0u16.to_le_bytes());
+        fields.extend(&0u16.to_le_bytes());
+        fields.extend(&0u16.to_le_bytes());
+        fields.extend(&0x5821u16.to_le_bytes());
+        fields.extend(&crc32(data).to_le_bytes());
+        fields.extend(&(data.len() as u32).to_le_bytes());
+        fields.extend(&(data.len() as u32).to_le_bytes());
+        fields.extend(&(name.len() as u16).to_le_bytes());
+        fields.extend(&0u16.to_le_bytes());
+
+        central_offsets.push(central_directory.len());
+        central_directory.extend(&0x02014b50u32.to_le_bytes());
+        central_directory.extend(&20u16.to_le_bytes());
+        central_directory.extend(&fields);
+        // comment length, disk number start, internal attributes
+        central_directory.extend(&[0; 6]);
+        central_directory.extend(&0u32.to_le_bytes());
+        central_directory.extend(&(zip.len() as u32).to_le_bytes());
+        central_directory.extend(name.as_bytes());
+
+        zip.extend(&0x04034b50u32.to_le_bytes());
+        zip.extend(&fields);
+        zip.extend(name.as_bytes());
+        zip.extend(*data);
+    }
+    let central_directory_offset = zip.len();
+    let central_offsets = central_offsets.iter().map(|offset| central_directory_offset + offset).collect();
+    zip.extend(&central_directory);
+    zip.extend(&0x06054b50u32.to_le_bytes());
+    zip.extend(&[0; 4]);
+    zip.extend(&(entries.len() as u16).to_le_bytes());
+    zip.extend(&(entries.len() as u16).to_le_bytes());
+    zip.extend(&(central_directory.len() as u32).to_le_bytes());
+    zip.extend(&(central_directory_offset as u32).to_le_bytes());
+    zip.extend(&0u16.to_le_bytes());
+    (zip, central_offsets)
+}
+
+fn sample_zip() -> (Vec<u8>, Vec<usize>) {
+    build_zip(&[("first.txt", b"the first entry\n"), ("second.txt", b"the second entry\n")])
+}
+
+fn run_test_mode(name: &str, zip: &[u8]) -> Output {
+    let path = std::env::temp_dir().join(format!("myunzip_test_mode_{}_{}.zip", std::process::id(), name));
+    fs::write(&path, zip).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_myunzip")).arg("-t").arg(&path).output().unwrap();
+    fs::remove_file(&path).unwrap();
+    output
+}
+
+fn assert_failed(output: &Output, failed_entry: &str) {
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(output.status.code(), Some(1), "{}", stdout);
+    assert!(stdout.lines().any(|line| line.contains(failed_entry) && line.contains("FAILED")), "{}", stdout);
+    assert!(stdout.contains("At least one error was detected"), "{}", stdout);
+}
+
+#[test]
+fn test_intact_archive() {
+    let output = run_test_mode("intact", &sample_zip().0);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{}", stdout);
+    assert_eq!(stdout.matches(" OK").count(), 2, "{}", stdout);
+    assert!(stdout.contains("No errors detected"), "{}", stdout);
+}
+
+#[test]
+fn test_truncated_archive() {
+    let zip = sample_zip().0;
+    let output = run_test_mode("truncated", &zip[..zip.len() / 2]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("End of central directory record not found"));
+}
+
+#[test]
+fn test_corrupt_crc() {
+    let (mut zip, central_offsets) = sample_zip();
+    let offset = central_offsets[1] + CENTRAL_CRC;
+    zip[offset] ^= 0xff;
+    assert_failed(&run_test_mode("crc", &zip), "second.txt");
+}
+
+#[test]
+fn test_corrupt_compressed_size() {
+    let (mut zip, central_offsets) = sample_zip();
+    let offset = central_offsets[0] + CENTRAL_COMPRESSED_SIZE;
+    zip[offset] -= 1;
+    assert_failed(&run_test_mode("size", &zip), "first.txt");
+}
+
+#[test]
+fn test_corrupt_local_header() {
+    // the modification time of the first local header
+    let (mut zip, _) = sample_zip();
+    zip[LOCAL_HEADER_TIME] ^= 0x01;
+    let output = run_test_mode("local", &zip);
+    assert_failed(&output, "first.txt");
+    assert!(String::from_utf8_lossy(&output.stdout).contains("modification time"));
+}
+
+#[test]
+fn test_different_version_needed() {
+    // writers may ask for a higher version in the central directory only
+    let (mut zip, central_offsets) = sample_zip();
+    zip[central_offsets[0] + CENTRAL_VERSION_NEEDED] = 45;
+    let output = run_test_mode("version", &zip);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{}", stdout);
+    assert_eq!(stdout.matches(" OK").count(), 2, "{}", stdout);
+}
@@ -0,0 +1,79 @@
+// the time zone comes from TZ only with the C library of Unix systems
+#![cfg(unix)]
+
+use std::fs;
+use std::process::Command;
+use std::time::UNIX_EPOCH;
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffffffffu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xedb88320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+// 2024-02-29 13:45:30 in the MS-DOS fields
+const DOS_DATE: u16 = (44 << 9) | (2 << 5) | 29;
+const DOS_TIME: u16 = (13 << 11) | (45 << 5) | 15;
+// the same date and time in UTC
+const UTC_SECONDS: u64 = 1709214330;
+
+// one stored entry made on MS-DOS, without an extended timestamp field
+fn build_zip(name: &str, data: &[u8]) -> Vec<u8> {
+    let mut fields = Vec::new();
+    fields.extend(&20u16.to_le_bytes());
+    fields.extend(&0u16.to_le_bytes());
+    fields.extend(&0u16.to_le_bytes());
+    fields.extend(&DOS_TIME.to_le_bytes());
+    fields.extend(&DOS_DATE.to_le_bytes());
+    fields.extend(&crc32(data).to_le_bytes());
+    fields.extend(&(data.len() as u32).to_le_bytes());
+    fields.extend(&(data.len() as u32).to_le_bytes());
+    fields.extend(&(name.len() as u16).to_le_bytes());
+    fields.extend(&0u16.to_le_bytes());
+
+    let mut zip = Vec::new();
+    zip.extend(&0x04034b50u32.to_le_bytes());
+    zip.extend(&fields);
+    zip.extend(name.as_bytes());
+    zip.extend(data);
+    let central_directory_offset = zip.len();
+    zip.extend(&0x02014b50u32.to_le_bytes());
+    zip.extend(&20u16.to_le_bytes());
+    zip.extend(&fields);
+    // comment length, disk number start, internal and external attributes, offset
+    zip.extend(&[0; 14]);
+    zip.extend(name.as_bytes());
+    let central_directory_size = zip.len() - central_directory_offset;
+    zip.extend(&0x06054b50u32.to_le_bytes());
+    zip.extend(&[0, 0, 0, 0, 1, 0, 1, 0]);
+    zip.extend(&(central_directory_size as u32).to_le_bytes());
+    zip.extend(&(central_directory_offset as u32).to_le_bytes());
+    zip.extend(&0u16.to_le_bytes());
+    zip
+}
+
+// the modification time of the entry extracted with the time zone tz
+fn extract_in_time_zone(tz: &str) -> u64 {
+    let dir = std::env::temp_dir().join(format!("myunzip_timestamps_{}_{}", std::process::id(), tz));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("test.zip"), build_zip("file.txt", b"local time\n")).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_myunzip"))
+        .arg("test.zip").current_dir(&dir).env("TZ", tz).output().unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let modified = fs::metadata(dir.join("file.txt")).unwrap().modified().unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    modified.duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
+
+#[test]
+fn test_dos_fields_are_local_time() {
+    assert_eq!(extract_in_time_zone("UTC0"), UTC_SECONDS);
+    // 13:45:30 in New York in winter is 18:45:30 UTC
+    assert_eq!(extract_in_time_zone("EST5"), UTC_SECONDS + 5 * 3600);
+    assert_eq!(extract_in_time_zone("JST-9"), UTC_SECONDS - 9 * 3600);
+}
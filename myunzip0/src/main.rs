@@ -1,7 +1,11 @@
+mod crc32;
+mod paths;
+
 use std::{env, fs};
 use std::fs::File;
 use std::io::{self, Read, Write, Cursor, Seek, SeekFrom};
-use std::path::Path;
+use crate::crc32::crc32;
+use crate::paths::{check_no_symlinks, sanitize_entry_name};
 
 fn read_u16_le(cursor: &mut Cursor<&[u8]>) -> io::Result<u16> {
     let mut bytes: [u8; 2] = [0u8; 2];
@@ -20,10 +24,23 @@ fn find_eocd(buffer: &[u8]) -> Option<usize> {
     buffer.windows(4).rposition(|window| window == eocd_signature)
 }
 
+fn verify_crc32(file_name: &str, expected_crc: u32, data: &[u8]) -> io::Result<()> {
+    let actual_crc = crc32(data);
+    if actual_crc != expected_crc {
+        return Err(io::Error::new(io::ErrorKind::InvalidData,
+            format!("CRC-32 mismatch for {}: expected {:08x}, got {:08x}", file_name, expected_crc, actual_crc)));
+    }
+    Ok(())
+}
+
 fn main() -> io::Result<()> {
-    let args: Vec<String> = env::args().collect();
+    let mut args: Vec<String> = env::args().collect();
+    let allow_unsafe_paths = args.len() == 3 && args[1] == "--allow-unsafe-paths";
+    if allow_unsafe_paths {
+        args.remove(1);
+    }
     if args.len() != 2 {
-        eprintln!("Usage: {} <input.zip>", args[0]);
+        eprintln!("Usage: {} [--allow-unsafe-paths] <input.zip>", args[0]);
         return Err(io::Error::new(io::ErrorKind::InvalidInput, "Incorrect number of arguments"));
     }
 
@@ -69,6 +86,11 @@ fn main() -> io::Result<()> {
 
         assert!(offset_of_start_of_central_directory == start_of_central_directory);
 
+        // the local header crc may be zero when a data descriptor is used, so trust the central directory
+        let mut cursor: Cursor<&[u8]> = Cursor::new(&buffer[offset_of_start_of_central_directory..]);
+        cursor.seek(SeekFrom::Current(16))?;
+        let expected_crc = read_u32_le(&mut cursor)?;
+
         let file_data = &buffer[start_of_data..offset_of_start_of_central_directory];
         let output_file_name = match compression_method {
             0 => file_name,
@@ -76,7 +98,16 @@ fn main() -> io::Result<()> {
             _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "Unsupported compression method")),
         };
 
-        let path = Path::new(&output_file_name);
+        // deflated entries are written out raw, so only stored entries can be checked here
+        if compression_method == 0 {
+            verify_crc32(&output_file_name, expected_crc, file_data)?;
+        }
+
+        let path = &sanitize_entry_name(&output_file_name, allow_unsafe_paths)?;
+        // a link already on disk must not redirect the output
+        if !allow_unsafe_paths {
+            check_no_symlinks(path)?;
+        }
         if let Some(parent) = path.parent() {
             fs::create_dir_all(parent)?;
         }
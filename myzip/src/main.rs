@@ -1,86 +1,427 @@
 mod bitwriter;
 mod lz77;
+mod crc32;
+mod timestamps;
+mod zip64;
 
-use std::env;
+use std::{env, fs};
 use std::fs::File;
-use std::io::{Write, BufReader, Read};
-use crate::bitwriter::BitWriter;
+use std::io::{self, Write, BufWriter, Read, Seek, SeekFrom};
+use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
+use crate::bitwriter::DeflateEncoder;
+use crate::crc32::{crc32, Crc32};
+use crate::lz77::CompressionLevel;
+use crate::timestamps::{get_extended_timestamp_fields, system_time_to_unix, unix_to_dos_date_time};
+use crate::zip64::{get_end_of_central_directory_records, get_version_needed, get_zip64_extra_field, get_zip64_field, ZIP64_LIMIT};
 
-fn main() -> std::io::Result<()> {
-    let args: Vec<String> = env::args().collect();
-    if args.len() != 3 {
-        eprintln!("Usage: {} <output.zip> <inputfile>", args[0]);
-        std::process::exit(1);
+// general purpose flag bit 11, the file name is UTF-8
+const FLAG_UTF8: u16 = 0x800;
+
+struct ZipEntry {
+    file_name: Vec<u8>,
+    flags: u16,
+    compression_method: u16,
+    last_mod_time: u16,
+    last_mod_date: u16,
+    crc: u32,
+    compressed_size: u64,
+    uncompressed_size: u64,
+    external_attributes: u32,
+    local_header_offset: u64,
+    zip64: bool, // the local header has the sizes in a ZIP64 extra field
+    version_needed: u16,
+    local_extra_field: Vec<u8>,   // extra fields other than ZIP64
+    central_extra_field: Vec<u8>,
+}
+
+#[cfg(unix)]
+fn get_unix_mode(metadata: &fs::Metadata) -> u32 {
+    use std::os::unix::fs::MetadataExt;
+    metadata.mode()
+}
+
+// file type and permissions as a Unix st_mode elsewhere
+#[cfg(not(unix))]
+fn get_unix_mode(metadata: &fs::Metadata) -> u32 {
+    let permissions = if metadata.permissions().readonly() { 0o444 } else { 0o644 };
+    if metadata.file_type().is_symlink() {
+        0o120777
+    } else if metadata.is_dir() {
+        0o040000 | permissions | 0o111
+    } else {
+        0o100000 | permissions
     }
+}
 
-    let output_zip = &args[1];
-    let input_file = &args[2];
+#[cfg(unix)]
+fn get_link_target_bytes(target: &Path) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt;
+    target.as_os_str().as_bytes().to_vec()
+}
+
+#[cfg(not(unix))]
+fn get_link_target_bytes(target: &Path) -> Vec<u8> {
+    target.to_string_lossy().replace('\\', "/").into_bytes()
+}
+
+// st_mode in the upper 16 bits, the MS-DOS attributes in the lowest byte
+fn get_external_attributes(metadata: &fs::Metadata) -> u32 {
+    let mut attributes = (get_unix_mode(metadata) & 0xffff) << 16;
+    if metadata.is_dir() {
+        attributes |= 0x10; // MS-DOS directory attribute
+    }
+    if metadata.permissions().readonly() {
+        attributes |= 0x01; // MS-DOS read-only attribute
+    }
+    attributes
+}
 
-    let mut file = BufReader::new(File::open(input_file)?);
-    let mut file_data = Vec::new();
-    file.read_to_end(&mut file_data)?;
+impl ZipEntry {
+    fn new(file_name: Vec<u8>, compression_method: u16, metadata: &fs::Metadata) -> Self {
+        let modified = system_time_to_unix(metadata.modified().unwrap_or_else(|_| SystemTime::now()));
+        let accessed = metadata.accessed().ok().map(system_time_to_unix);
+        let created = metadata.created().ok().map(system_time_to_unix);
+        // the MS-DOS fields are local time, the extended timestamp field has the exact time
+        let (last_mod_date, last_mod_time) = unix_to_dos_date_time(modified);
+        let (local_extra_field, central_extra_field) = get_extended_timestamp_fields(modified, accessed, created);
+        // names are always UTF-8, plain ASCII reads the same in CP437
+        let flags = if file_name.is_ascii() { 0 } else { FLAG_UTF8 };
+        ZipEntry {
+            file_name,
+            flags,
+            compression_method,
+            last_mod_time,
+            last_mod_date,
+            crc: 0,
+            compressed_size: 0,
+            uncompressed_size: 0,
+            external_attributes: get_external_attributes(metadata),
+            local_header_offset: 0,
+            zip64: false,
+            version_needed: 20,
+            local_extra_field,
+            central_extra_field,
+        }
+    }
 
-    let uncompressed_file_size = file_data.len() as u32;
-    let file_name = input_file.as_bytes();
-    let file_name_length = file_name.len() as u16;
+    // both headers take the version needed to extract from here, so an entry
+    // whose only ZIP64 field is the offset gets 4.5 in its local header too
+    fn set_local_header_offset(&mut self, local_header_offset: u64, zip64: bool) {
+        self.local_header_offset = local_header_offset;
+        self.zip64 = zip64;
+        self.version_needed = get_version_needed(zip64, local_header_offset);
+    }
+}
 
-    let mut bit_writer = BitWriter::new();
-    bit_writer.write_bitstream_fixed_huffman(&file_data)?;
-    let compressed_file_data = bit_writer.get_buffer();
-    let compressed_file_size = compressed_file_data.len() as u32;
+// zip names always use '/' and never start with '/', '.' or '..'
+fn get_entry_name(path: &Path) -> String {
+    let mut names: Vec<String> = Vec::new();
+    for component in path.components() {
+        if let Component::Normal(name) = component {
+            names.push(name.to_string_lossy().into_owned());
+        }
+    }
+    names.join("/")
+}
 
+fn get_local_header(entry: &ZipEntry) -> Vec<u8> {
+    // the local ZIP64 extra field always holds both sizes
+    let (compressed_size, uncompressed_size, mut extra_field) = if entry.zip64 {
+        (0xffffffffu32, 0xffffffffu32, get_zip64_extra_field(&[entry.uncompressed_size, entry.compressed_size]))
+    } else {
+        (entry.compressed_size as u32, entry.uncompressed_size as u32, Vec::new())
+    };
+    extra_field.extend(&entry.local_extra_field);
     let mut local_header = Vec::new();
     local_header.extend(&0x04034b50u32.to_le_bytes());
-    local_header.extend(&20u16.to_le_bytes());
-    local_header.extend(&0u16.to_le_bytes());
-    local_header.extend(&8u16.to_le_bytes()); //use deflate to compress
-    local_header.extend(&0u16.to_le_bytes());
-    local_header.extend(&0u16.to_le_bytes());
-    local_header.extend(&0xdeadbeefu32.to_le_bytes());
-    local_header.extend(&compressed_file_size.to_le_bytes());
-    local_header.extend(&uncompressed_file_size.to_le_bytes());
-    local_header.extend(&file_name_length.to_le_bytes());
-    local_header.extend(&0u16.to_le_bytes());
-    local_header.extend(file_name);
-
-    // file data added in the end
+    local_header.extend(&entry.version_needed.to_le_bytes());
+    local_header.extend(&entry.flags.to_le_bytes());
+    local_header.extend(&entry.compression_method.to_le_bytes());
+    local_header.extend(&entry.last_mod_time.to_le_bytes());
+    local_header.extend(&entry.last_mod_date.to_le_bytes());
+    local_header.extend(&entry.crc.to_le_bytes());
+    local_header.extend(&compressed_size.to_le_bytes());
+    local_header.extend(&uncompressed_size.to_le_bytes());
+    local_header.extend(&(entry.file_name.len() as u16).to_le_bytes());
+    local_header.extend(&(extra_field.len() as u16).to_le_bytes());
+    local_header.extend(&entry.file_name);
+    local_header.extend(&extra_field);
+    local_header
+}
+
+fn get_central_directory_header(entry: &ZipEntry) -> Vec<u8> {
+    // the ZIP64 values follow the order uncompressed size, compressed size, offset
+    let mut zip64_values = Vec::new();
+    let uncompressed_size = get_zip64_field(entry.uncompressed_size, &mut zip64_values);
+    let compressed_size = get_zip64_field(entry.compressed_size, &mut zip64_values);
+    let local_header_offset = get_zip64_field(entry.local_header_offset, &mut zip64_values);
+    let mut extra_field = get_zip64_extra_field(&zip64_values);
+    extra_field.extend(&entry.central_extra_field);
 
     let mut central_dir = Vec::new();
     central_dir.extend(&0x02014b50u32.to_le_bytes());
+    // made by Unix, zip specification 3.0
     central_dir.extend(&30u8.to_le_bytes());
-    central_dir.extend(&65u8.to_le_bytes());
-    central_dir.extend(&20u16.to_le_bytes());
-    central_dir.extend(&0u16.to_le_bytes());
-    central_dir.extend(&8u16.to_le_bytes()); //use deflate to compress
-    central_dir.extend(&0u16.to_le_bytes());
-    central_dir.extend(&0u16.to_le_bytes());
-    central_dir.extend(&0xdeadbeefu32.to_le_bytes());
-    central_dir.extend(&compressed_file_size.to_le_bytes());
-    central_dir.extend(&uncompressed_file_size.to_le_bytes());
-    central_dir.extend(&file_name_length.to_le_bytes());
+    central_dir.extend(&3u8.to_le_bytes());
+    central_dir.extend(&entry.version_needed.to_le_bytes());
+    central_dir.extend(&entry.flags.to_le_bytes());
+    central_dir.extend(&entry.compression_method.to_le_bytes());
+    central_dir.extend(&entry.last_mod_time.to_le_bytes());
+    central_dir.extend(&entry.last_mod_date.to_le_bytes());
+    central_dir.extend(&entry.crc.to_le_bytes());
+    central_dir.extend(&compressed_size.to_le_bytes());
+    central_dir.extend(&uncompressed_size.to_le_bytes());
+    central_dir.extend(&(entry.file_name.len() as u16).to_le_bytes());
+    central_dir.extend(&(extra_field.len() as u16).to_le_bytes());
     central_dir.extend(&0u16.to_le_bytes());
     central_dir.extend(&0u16.to_le_bytes());
     central_dir.extend(&0u16.to_le_bytes());
-    central_dir.extend(&1u16.to_le_bytes());
-    central_dir.extend(&1u32.to_le_bytes());
-    central_dir.extend(&0u32.to_le_bytes());
-    central_dir.extend(file_name);
-
-    let mut end_central_dir = Vec::new();
-    end_central_dir.extend(&0x06054b50u32.to_le_bytes());
-    end_central_dir.extend(&0u16.to_le_bytes());
-    end_central_dir.extend(&0u16.to_le_bytes());
-    end_central_dir.extend(&1u16.to_le_bytes());
-    end_central_dir.extend(&1u16.to_le_bytes());
-    end_central_dir.extend(&(central_dir.len() as u32).to_le_bytes());
-    end_central_dir.extend(&(local_header.len() as u32 + compressed_file_size).to_le_bytes());
-    end_central_dir.extend(&0u16.to_le_bytes());
-
-    let mut output = File::create(output_zip)?;
-    output.write_all(&local_header)?;
-    output.write_all(&compressed_file_data)?;
-    output.write_all(&central_dir)?;
-    output.write_all(&end_central_dir)?;
-
-    Ok(())
+    central_dir.extend(&entry.external_attributes.to_le_bytes());
+    central_dir.extend(&local_header_offset.to_le_bytes());
+    central_dir.extend(&entry.file_name);
+    central_dir.extend(&extra_field);
+    central_dir
+}
+
+// copy everything from reader to writer, returns the number of bytes and their CRC-32
+fn copy_with_crc32(reader: &mut dyn Read, writer: &mut dyn Write) -> io::Result<(u64, u32)> {
+    let mut buffer = vec![0u8; 1 << 16];
+    let mut crc = Crc32::new();
+    let mut size: u64 = 0;
+    loop {
+        let count = match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(count) => count,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        crc.update(&buffer[..count]);
+        writer.write_all(&buffer[..count])?;
+        size += count as u64;
+    }
+    Ok((size, crc.finalize()))
+}
+
+// "-0" to "-9" like zip
+fn parse_level(arg: &str) -> Option<u8> {
+    let digit = arg.strip_prefix('-')?;
+    if digit.len() == 1 && digit.as_bytes()[0].is_ascii_digit() {
+        Some(digit.as_bytes()[0] - b'0')
+    } else {
+        None
+    }
+}
+
+struct ZipWriter {
+    output: BufWriter<File>,
+    output_path: PathBuf,
+    level: CompressionLevel,
+    follow_symlinks: bool,
+    directory_stack: Vec<PathBuf>, // canonical paths of the directories being added
+    offset: u64,
+    entries: Vec<ZipEntry>,
+}
+
+impl ZipWriter {
+    fn new(output_zip: &str, level: CompressionLevel, follow_symlinks: bool) -> io::Result<Self> {
+        let output = BufWriter::new(File::create(output_zip)?);
+        Ok(ZipWriter {
+            output,
+            output_path: fs::canonicalize(output_zip)?,
+            level,
+            follow_symlinks,
+            directory_stack: Vec::new(),
+            offset: 0,
+            entries: Vec::new(),
+        })
+    }
+
+    fn write_entry(&mut self, mut entry: ZipEntry, data: &[u8]) -> io::Result<()> {
+        entry.set_local_header_offset(self.offset, false);
+        let local_header = get_local_header(&entry);
+        self.output.write_all(&local_header)?;
+        self.output.write_all(data)?;
+        self.offset += local_header.len() as u64 + data.len() as u64;
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    fn add_directory(&mut self, path: &Path) -> io::Result<()> {
+        let file_name = get_entry_name(path) + "/";
+        let entry = ZipEntry::new(file_name.into_bytes(), 0, &fs::metadata(path)?);
+        self.write_entry(entry, &[])
+    }
+
+    // the link itself with its target as data, like Info-ZIP stores it
+    fn add_symlink(&mut self, path: &Path, metadata: &fs::Metadata) -> io::Result<()> {
+        let target = get_link_target_bytes(&fs::read_link(path)?);
+        let mut entry = ZipEntry::new(get_entry_name(path).into_bytes(), 0, metadata);
+        entry.crc = crc32(&target);
+        entry.compressed_size = target.len() as u64;
+        entry.uncompressed_size = target.len() as u64;
+        self.write_entry(entry, &target)
+    }
+
+    // the file is compressed straight into the archive, the crc and sizes
+    // are only known afterwards so the local header is written twice;
+    // files that deflate makes larger are stored instead
+    fn add_file(&mut self, path: &Path) -> io::Result<()> {
+        let mut file = File::open(path)?;
+        let metadata = file.metadata()?;
+        //use deflate to compress
+        let compression_method = if self.level.store { 0 } else { 8 };
+        let mut entry = ZipEntry::new(get_entry_name(path).into_bytes(), compression_method, &metadata);
+        // the local header cannot grow once the data follows it
+        let zip64 = metadata.len() >= ZIP64_LIMIT;
+        entry.set_local_header_offset(self.offset, zip64);
+        let local_header = get_local_header(&entry);
+        self.output.write_all(&local_header)?;
+
+        let (uncompressed_size, crc) = if self.level.store {
+            copy_with_crc32(&mut file, &mut self.output)?
+        } else {
+            let mut encoder = DeflateEncoder::with_level(&mut self.output, self.level);
+            let result = copy_with_crc32(&mut file, &mut encoder)?;
+            encoder.finish()?;
+            result
+        };
+
+        let data_start = self.offset + local_header.len() as u64;
+        let mut data_end = self.output.stream_position()?;
+        if entry.compression_method == 8 && data_end - data_start > uncompressed_size {
+            // write the file again over the deflated data, without compression
+            self.output.seek(SeekFrom::Start(data_start))?;
+            file.seek(SeekFrom::Start(0))?;
+            let (stored_size, stored_crc) = copy_with_crc32(&mut file, &mut self.output)?;
+            if stored_size != uncompressed_size || stored_crc != crc {
+                return Err(io::Error::other(format!("{} changed while being added", path.display())));
+            }
+            data_end = self.output.stream_position()?;
+            // the end of the deflated data would be left behind a last entry
+            self.output.flush()?;
+            self.output.get_ref().set_len(data_end)?;
+            entry.compression_method = 0;
+        }
+        if !zip64 && uncompressed_size >= ZIP64_LIMIT {
+            return Err(io::Error::other(format!("{} grew past 4 GiB while being added", path.display())));
+        }
+        entry.crc = crc;
+        entry.compressed_size = data_end - data_start;
+        entry.uncompressed_size = uncompressed_size;
+        self.output.seek(SeekFrom::Start(self.offset))?;
+        self.output.write_all(&get_local_header(&entry))?;
+        self.output.seek(SeekFrom::Start(data_end))?;
+
+        self.offset = data_end;
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    // directories are added before their contents, children in sorted order
+    fn add_path(&mut self, path: &Path) -> io::Result<()> {
+        // symbolic links are stored as links unless they are followed
+        let metadata = fs::symlink_metadata(path)?;
+        if metadata.file_type().is_symlink() && !self.follow_symlinks {
+            return self.add_symlink(path, &metadata);
+        }
+        if metadata.file_type().is_symlink() && !path.exists() {
+            eprintln!("{}: skipped, broken symbolic link", path.display());
+            return Ok(());
+        }
+        // never add the archive being written to itself
+        let canonical_path = fs::canonicalize(path)?;
+        if canonical_path == self.output_path {
+            return Ok(());
+        }
+        if path.is_dir() {
+            // a followed link to a directory that contains it would never end
+            if self.directory_stack.contains(&canonical_path) {
+                eprintln!("{}: skipped, directory loop", path.display());
+                return Ok(());
+            }
+            if !get_entry_name(path).is_empty() {
+                self.add_directory(path)?;
+            }
+            let mut children: Vec<_> = fs::read_dir(path)?
+                .map(|entry| entry.map(|e| e.path()))
+                .collect::<io::Result<_>>()?;
+            children.sort();
+            self.directory_stack.push(canonical_path);
+            for child in children {
+                self.add_path(&child)?;
+            }
+            self.directory_stack.pop();
+            Ok(())
+        } else {
+            self.add_file(path)
+        }
+    }
+
+    fn finish(mut self) -> io::Result<()> {
+        let central_dir_offset = self.offset;
+        let mut central_dir_size: u64 = 0;
+        for entry in &self.entries {
+            let central_dir = get_central_directory_header(entry);
+            self.output.write_all(&central_dir)?;
+            central_dir_size += central_dir.len() as u64;
+        }
+
+        let end_records = get_end_of_central_directory_records(self.entries.len() as u64, central_dir_size,
+            central_dir_offset);
+        self.output.write_all(&end_records)?;
+        self.output.flush()
+    }
+}
+
+fn main() -> std::io::Result<()> {
+    let mut args: Vec<String> = env::args().collect();
+    let mut level = CompressionLevel::default();
+    let mut follow_symlinks = false;
+    while args.len() > 1 && args[1].starts_with('-') {
+        if let Some(number) = parse_level(&args[1]) {
+            level = CompressionLevel::new(number);
+        } else if args[1] == "--follow" {
+            follow_symlinks = true;
+        } else {
+            break;
+        }
+        args.remove(1);
+    }
+    if args.len() < 3 {
+        eprintln!("Usage: {} [-0..-9] [--follow] <output.zip> <inputfile|directory>...", args[0]);
+        std::process::exit(1);
+    }
+
+    let output_zip = &args[1];
+    let mut zip_writer = ZipWriter::new(output_zip, level, follow_symlinks)?;
+    for input in &args[2..] {
+        zip_writer.add_path(Path::new(input))?;
+    }
+    zip_writer.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_external_attributes() {
+        use std::os::unix::fs::PermissionsExt;
+        let path = std::env::temp_dir().join(format!("myzip_attributes_test_{}", std::process::id()));
+        File::create(&path).unwrap();
+        // the whole st_mode is kept, setuid and sticky bits included
+        for mode in [0o644, 0o4755, 0o2750, 0o1777, 0o444] {
+            fs::set_permissions(&path, fs::Permissions::from_mode(mode)).unwrap();
+            let attributes = get_external_attributes(&fs::metadata(&path).unwrap());
+            assert_eq!(attributes >> 16, 0o100000 | mode);
+            assert_eq!(attributes & 0x01, if mode & 0o200 == 0 { 0x01 } else { 0 });
+        }
+        fs::remove_file(&path).unwrap();
+
+        let attributes = get_external_attributes(&fs::metadata(std::env::temp_dir()).unwrap());
+        assert_eq!(attributes >> 16 & 0o170000, 0o040000);
+        assert_eq!(attributes & 0x10, 0x10);
+    }
 }
@@ -0,0 +1,198 @@
+// sizes and offsets from this value on are stored in the ZIP64 extra field
+pub const ZIP64_LIMIT: u64 = 0xffffffff;
+
+// the ZIP64 extended information extra field with the given 64 bit values
+pub fn get_zip64_extra_field(values: &[u64]) -> Vec<u8> {
+    let mut extra_field = Vec::new();
+    if values.is_empty() {
+        return extra_field;
+    }
+    extra_field.extend(&0x0001u16.to_le_bytes());
+    extra_field.extend(&(values.len() as u16 * 8).to_le_bytes());
+    for value in values {
+        extra_field.extend(&value.to_le_bytes());
+    }
+    extra_field
+}
+
+// value as a 32 bit field, or 0xffffffff with the value moved to zip64_values
+pub fn get_zip64_field(value: u64, zip64_values: &mut Vec<u64>) -> u32 {
+    if value >= ZIP64_LIMIT {
+        zip64_values.push(value);
+        0xffffffff
+    } else {
+        value as u32
+    }
+}
+
+// version 4.5 for an entry with any ZIP64 field, in the local and the central header
+pub fn get_version_needed(zip64: bool, local_header_offset: u64) -> u16 {
+    if zip64 || local_header_offset >= ZIP64_LIMIT { 45 } else { 20 }
+}
+
+fn get_end_of_central_directory(num_entries: u16, central_dir_size: u32, central_dir_offset: u32) -> Vec<u8> {
+    let mut end_central_dir = Vec::new();
+    end_central_dir.extend(&0x06054b50u32.to_le_bytes());
+    end_central_dir.extend(&0u16.to_le_bytes());
+    end_central_dir.extend(&0u16.to_le_bytes());
+    end_central_dir.extend(&num_entries.to_le_bytes());
+    end_central_dir.extend(&num_entries.to_le_bytes());
+    end_central_dir.extend(&central_dir_size.to_le_bytes());
+    end_central_dir.extend(&central_dir_offset.to_le_bytes());
+    end_central_dir.extend(&0u16.to_le_bytes());
+    end_central_dir
+}
+
+fn get_zip64_end_of_central_directory(num_entries: u64, central_dir_size: u64, central_dir_offset: u64) -> Vec<u8> {
+    let mut end_central_dir = Vec::new();
+    end_central_dir.extend(&0x06064b50u32.to_le_bytes());
+    // size of the rest of the record
+    end_central_dir.extend(&44u64.to_le_bytes());
+    end_central_dir.extend(&30u8.to_le_bytes());
+    end_central_dir.extend(&3u8.to_le_bytes());
+    end_central_dir.extend(&45u16.to_le_bytes());
+    end_central_dir.extend(&0u32.to_le_bytes());
+    end_central_dir.extend(&0u32.to_le_bytes());
+    end_central_dir.extend(&num_entries.to_le_bytes());
+    end_central_dir.extend(&num_entries.to_le_bytes());
+    end_central_dir.extend(&central_dir_size.to_le_bytes());
+    end_central_dir.extend(&central_dir_offset.to_le_bytes());
+    end_central_dir
+}
+
+fn get_zip64_end_of_central_directory_locator(zip64_end_central_dir_offset: u64) -> Vec<u8> {
+    let mut locator = Vec::new();
+    locator.extend(&0x07064b50u32.to_le_bytes());
+    locator.extend(&0u32.to_le_bytes());
+    locator.extend(&zip64_end_central_dir_offset.to_le_bytes());
+    // total number of disks
+    locator.extend(&1u32.to_le_bytes());
+    locator
+}
+
+
+// the records after the central directory; counts and sizes that do not fit are
+// 0xffff or 0xffffffff in the end of central directory record and found in the
+// ZIP64 record before it
+pub fn get_end_of_central_directory_records(num_entries: u64, central_dir_size: u64, central_dir_offset: u64) -> Vec<u8> {
+    let mut records = Vec::new();
+    if num_entries >= 0xffff || central_dir_size >= ZIP64_LIMIT || central_dir_offset >= ZIP64_LIMIT {
+        let zip64_end_central_dir_offset = central_dir_offset + central_dir_size;
+        records.extend(get_zip64_end_of_central_directory(num_entries, central_dir_size, central_dir_offset));
+        records.extend(get_zip64_end_of_central_directory_locator(zip64_end_central_dir_offset));
+    }
+    records.extend(get_end_of_central_directory(num_entries.min(0xffff) as u16,
+        central_dir_size.min(ZIP64_LIMIT) as u32, central_dir_offset.min(ZIP64_LIMIT) as u32));
+    records
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_u16(data: &[u8], position: usize) -> u16 {
+        u16::from_le_bytes(data[position..position + 2].try_into().unwrap())
+    }
+
+    fn read_u32(data: &[u8], position: usize) -> u32 {
+        u32::from_le_bytes(data[position..position + 4].try_into().unwrap())
+    }
+
+    fn read_u64(data: &[u8], position: usize) -> u64 {
+        u64::from_le_bytes(data[position..position + 8].try_into().unwrap())
+    }
+
+    #[test]
+    fn test_zip64_fields() {
+        let mut zip64_values = Vec::new();
+        assert_eq!(get_zip64_field(ZIP64_LIMIT - 1, &mut zip64_values), 0xfffffffe);
+        assert_eq!(get_zip64_field(ZIP64_LIMIT, &mut zip64_values), 0xffffffff);
+        assert_eq!(get_zip64_field(5 << 30, &mut zip64_values), 0xffffffff);
+        assert_eq!(zip64_values, [ZIP64_LIMIT, 5 << 30]);
+
+        let extra_field = get_zip64_extra_field(&zip64_values);
+        assert_eq!(extra_field.len(), 4 + 16);
+        assert_eq!((read_u16(&extra_field, 0), read_u16(&extra_field, 2)), (0x0001, 16));
+        assert_eq!((read_u64(&extra_field, 4), read_u64(&extra_field, 12)), (ZIP64_LIMIT, 5 << 30));
+        assert!(get_zip64_extra_field(&[]).is_empty());
+    }
+
+    fn get_entry(size: u64) -> crate::ZipEntry {
+        crate::ZipEntry {
+            file_name: b"small.txt".to_vec(),
+            flags: 0,
+            compression_method: 0,
+            last_mod_time: 0,
+            last_mod_date: 0x21,
+            crc: 0,
+            compressed_size: size,
+            uncompressed_size: size,
+            external_attributes: 0,
+            local_header_offset: 0,
+            zip64: false,
+            version_needed: 20,
+            local_extra_field: Vec::new(),
+            central_extra_field: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_version_needed() {
+        // a small entry stored after the first 4 GiB, only its offset is a ZIP64 field
+        let mut entry = get_entry(100);
+        entry.set_local_header_offset(5 << 30, false);
+        let local_header = crate::get_local_header(&entry);
+        let central_header = crate::get_central_directory_header(&entry);
+        assert_eq!(read_u16(&local_header, 26 + 2), 0);
+        assert_eq!(read_u16(&central_header, 30), 12);
+        assert_eq!((read_u16(&local_header, 4), read_u16(&central_header, 6)), (45, 45));
+
+        let mut entry = get_entry(100);
+        entry.set_local_header_offset(ZIP64_LIMIT - 1, false);
+        let (local_header, central_header) = (crate::get_local_header(&entry), crate::get_central_directory_header(&entry));
+        assert_eq!((read_u16(&local_header, 4), read_u16(&central_header, 6)), (20, 20));
+
+        let mut entry = get_entry(5 << 30);
+        entry.set_local_header_offset(0, true);
+        let (local_header, central_header) = (crate::get_local_header(&entry), crate::get_central_directory_header(&entry));
+        assert_eq!((read_u16(&local_header, 4), read_u16(&central_header, 6)), (45, 45));
+    }
+
+    #[test]
+    fn test_small_archive_end_records() {
+        let records = get_end_of_central_directory_records(3, 200, 1000);
+        assert_eq!(records.len(), 22);
+        assert_eq!(read_u32(&records, 0), 0x06054b50);
+        assert_eq!((read_u16(&records, 8), read_u16(&records, 10)), (3, 3));
+        assert_eq!((read_u32(&records, 12), read_u32(&records, 16)), (200, 1000));
+    }
+
+    #[test]
+    fn test_zip64_end_records() {
+        // too many entries, and the central directory starts past 4 GiB
+        let (num_entries, central_dir_size, central_dir_offset) = (70000, 3_500_000, 5 << 30);
+        let records = get_end_of_central_directory_records(num_entries, central_dir_size, central_dir_offset);
+        assert_eq!(records.len(), 56 + 20 + 22);
+
+        assert_eq!(read_u32(&records, 0), 0x06064b50);
+        assert_eq!(read_u64(&records, 4), 44);
+        assert_eq!((read_u64(&records, 24), read_u64(&records, 32)), (num_entries, num_entries));
+        assert_eq!((read_u64(&records, 40), read_u64(&records, 48)), (central_dir_size, central_dir_offset));
+
+        // the locator points to the ZIP64 record right after the central directory
+        assert_eq!(read_u32(&records, 56), 0x07064b50);
+        assert_eq!(read_u64(&records, 64), central_dir_offset + central_dir_size);
+        assert_eq!(read_u32(&records, 72), 1);
+
+        let eocd = &records[76..];
+        assert_eq!(read_u32(eocd, 0), 0x06054b50);
+        assert_eq!((read_u16(eocd, 8), read_u16(eocd, 10)), (0xffff, 0xffff));
+        assert_eq!((read_u32(eocd, 12), read_u32(eocd, 16)), (central_dir_size as u32, 0xffffffff));
+
+        // each threshold on its own is enough
+        assert_eq!(get_end_of_central_directory_records(0xfffe, 100, 100).len(), 22);
+        assert_eq!(get_end_of_central_directory_records(0xffff, 100, 100).len(), 98);
+        assert_eq!(get_end_of_central_directory_records(1, ZIP64_LIMIT, 100).len(), 98);
+        assert_eq!(get_end_of_central_directory_records(1, 100, ZIP64_LIMIT).len(), 98);
+    }
+}
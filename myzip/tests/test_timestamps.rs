@@ -0,0 +1,35 @@
+// the time zone comes from TZ only with the C library of Unix systems
+#![cfg(unix)]
+
+use std::fs::{self, File};
+use std::process::Command;
+use std::time::{Duration, UNIX_EPOCH};
+
+// offsets of the MS-DOS time and date in the local header
+const LOCAL_HEADER_TIME: usize = 10;
+const LOCAL_HEADER_DATE: usize = 12;
+
+// the MS-DOS (date, time) of a file modified at 2024-02-29 13:45:30 UTC, archived
+// with the time zone tz
+fn archive_in_time_zone(tz: &str) -> (u16, u16) {
+    let dir = std::env::temp_dir().join(format!("myzip_timestamps_{}_{}", std::process::id(), tz));
+    fs::create_dir_all(&dir).unwrap();
+    let file = File::create(dir.join("file.txt")).unwrap();
+    file.set_modified(UNIX_EPOCH + Duration::from_secs(1709214330)).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_myzip"))
+        .args(["-0", "test.zip", "file.txt"]).current_dir(&dir).env("TZ", tz).output().unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let zip = fs::read(dir.join("test.zip")).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    let read_u16 = |position: usize| u16::from_le_bytes([zip[position], zip[position + 1]]);
+    (read_u16(LOCAL_HEADER_DATE), read_u16(LOCAL_HEADER_TIME))
+}
+
+#[test]
+fn test_dos_fields_are_local_time() {
+    let date = (44 << 9) | (2 << 5) | 29;
+    assert_eq!(archive_in_time_zone("UTC0"), (date, (13 << 11) | (45 << 5) | 15));
+    assert_eq!(archive_in_time_zone("EST5"), (date, (8 << 11) | (45 << 5) | 15));
+    // 22:45:30 in Tokyo, still the same day
+    assert_eq!(archive_in_time_zone("JST-9"), (date, (22 << 11) | (45 << 5) | 15));
+}
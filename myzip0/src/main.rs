@@ -1,6 +1,9 @@
+mod crc32;
+
 use std::env;
 use std::fs::File;
 use std::io::{Write, BufReader, Read};
+use crate::crc32::crc32;
 
 fn main() -> std::io::Result<()> {
     let args: Vec<String> = env::args().collect();
@@ -17,6 +20,7 @@ fn main() -> std::io::Result<()> {
     file.read_to_end(&mut file_data)?;
 
     let file_size = file_data.len() as u32;
+    let crc = crc32(&file_data);
     let file_name = input_file.as_bytes();
     let file_name_length = file_name.len() as u16;
 
@@ -27,7 +31,7 @@ fn main() -> std::io::Result<()> {
     local_header.extend(&0u16.to_le_bytes());
     local_header.extend(&0u16.to_le_bytes());
     local_header.extend(&0u16.to_le_bytes());
-    local_header.extend(&0xdeadbeefu32.to_le_bytes());
+    local_header.extend(&crc.to_le_bytes());
     local_header.extend(&file_size.to_le_bytes());
     local_header.extend(&file_size.to_le_bytes());
     local_header.extend(&file_name_length.to_le_bytes());
@@ -45,7 +49,7 @@ fn main() -> std::io::Result<()> {
     central_dir.extend(&0u16.to_le_bytes());
     central_dir.extend(&0u16.to_le_bytes());
     central_dir.extend(&0u16.to_le_bytes());
-    central_dir.extend(&0xdeadbeefu32.to_le_bytes());
+    central_dir.extend(&crc.to_le_bytes());
     central_dir.extend(&file_size.to_le_bytes());
     central_dir.extend(&file_size.to_le_bytes());
     central_dir.extend(&file_name_length.to_le_bytes());